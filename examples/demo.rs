@@ -1,9 +1,7 @@
-pub mod tuihtml;
-
 use color_eyre::Result;
 use crossterm::event::{self, Event};
 use ratatui::{DefaultTerminal, Frame};
-use crate::tuihtml::widget::HtmlWidget;
+use tui_html::{HtmlWidget, ParseOptions, Theme};
 
 fn main() -> Result<()> {
     color_eyre::install()?;
@@ -121,7 +119,7 @@ fn run(mut terminal: DefaultTerminal) -> Result<()> {
     </p>
 </footer>
 "##;
-    let html_widget = HtmlWidget::new(String::from(html));
+    let (html_widget, _) = HtmlWidget::build(html, Some(&Theme::for_terminal()), Some(&ParseOptions::default()));
 
     loop {
         terminal.draw(|f| render(f, &html_widget))?;
@@ -133,7 +131,7 @@ fn run(mut terminal: DefaultTerminal) -> Result<()> {
 
 fn render(frame: &mut Frame, html_widget: &HtmlWidget) {
 
-    frame.render_widget(html_widget.get_paragraph(), frame.area());
+    frame.render_widget(html_widget, frame.area());
 }
 
 
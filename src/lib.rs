@@ -0,0 +1,27 @@
+//! Renders HTML as Ratatui widgets.
+//!
+//! ```no_run
+//! fn draw(frame: &mut ratatui::Frame) {
+//!     let widget = tui_html::HtmlWidget::new("<p>Hello <b>World</b></p>");
+//!     frame.render_widget(&widget, frame.area());
+//! }
+//! ```
+//!
+//! Below the widget sit the tokenizer it is built on and the `Document` it
+//! reads, which can be used on their own.
+
+mod tuihtml;
+
+pub use tuihtml::{
+    color::{Background, ColorDepth, Palette},
+    diagnostics::{ParseError, ParseErrorKind},
+    dom::{Document, Node, NodeData, NodeEdge, NodeId},
+    error::{Error, Result},
+    html::{Attributes, HtmlElement, HtmlTag},
+    options::{Limit, ParseOptions},
+    source::{SourcePosition, SourceSpan},
+    stream::HtmlStream,
+    theme::{BlockStyle, ElementStyle, Rule, RuleWidth, TableBorder, Theme},
+    tokenizer::{tokenize, HtmlTokenizer, Token},
+    widget::HtmlWidget,
+};
@@ -0,0 +1,545 @@
+use ratatui::style::{Color, Style};
+
+// Text needs at least this contrast ratio against its background, the
+// WCAG AA level for body text.
+const MIN_CONTRAST: f32 = 4.5;
+
+// The colors a terminal can show, fewest last.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ColorDepth {
+    #[default]
+    TrueColor,
+    Ansi256,
+    Ansi16,
+    // No colors at all, as NO_COLOR asks for. Bold and the other modifiers
+    // are kept.
+    Monochrome,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+    Dark,
+    Light,
+}
+
+// How colors from the page and the theme are fitted to the terminal. The
+// default leaves them as they are.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Palette {
+    pub depth: ColorDepth,
+    // The terminal's background, when known. Text colors too close to it, or
+    // to the background color of the text itself, are made lighter or darker
+    // until they can be read.
+    pub background: Option<Background>,
+}
+
+impl ColorDepth {
+    // What the environment says the terminal supports: NO_COLOR turns colors
+    // off, COLORTERM tells of true color and TERM of the rest.
+    pub fn detect() -> Self {
+        ColorDepth::from_env(|name| std::env::var(name).ok())
+    }
+
+    pub(crate) fn from_env(var: impl Fn(&str) -> Option<String>) -> Self {
+        if var("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+            return ColorDepth::Monochrome;
+        }
+        if var("COLORTERM").is_some_and(|value| value == "truecolor" || value == "24bit") {
+            return ColorDepth::TrueColor;
+        }
+
+        match var("TERM") {
+            None => ColorDepth::Monochrome,
+            Some(term) if term.is_empty() || term == "dumb" => ColorDepth::Monochrome,
+            Some(term) if term.contains("256color") => ColorDepth::Ansi256,
+            Some(_) => ColorDepth::Ansi16,
+        }
+    }
+}
+
+impl Background {
+    // From COLORFGBG, which some terminals set to e.g. `15;0` for light text
+    // on a dark background.
+    pub fn detect() -> Option<Self> {
+        Background::from_colorfgbg(&std::env::var("COLORFGBG").ok()?)
+    }
+
+    pub(crate) fn from_colorfgbg(value: &str) -> Option<Self> {
+        match value.rsplit(';').next()?.trim().parse::<u8>().ok()? {
+            7 | 9..=15 => Some(Background::Light),
+            0..=6 | 8 => Some(Background::Dark),
+            _ => None,
+        }
+    }
+
+    fn rgb(&self) -> (u8, u8, u8) {
+        match self {
+            Background::Dark => (0, 0, 0),
+            Background::Light => (255, 255, 255),
+        }
+    }
+}
+
+impl Palette {
+    // What the environment says about the terminal.
+    pub fn detect() -> Self {
+        Palette {
+            depth: ColorDepth::detect(),
+            background: Background::detect(),
+        }
+    }
+
+    pub fn adapt(&self, mut style: Style) -> Style {
+        if let (Some(background), Some(fg)) = (self.background, style.fg) {
+            let behind = style.bg.and_then(to_rgb).unwrap_or(background.rgb());
+            style.fg = Some(with_contrast(fg, behind));
+        }
+
+        style.fg = style.fg.and_then(|color| self.adapt_color(color));
+        style.bg = style.bg.and_then(|color| self.adapt_color(color));
+        style
+    }
+
+    // The nearest color the terminal can show, None when it shows none.
+    pub fn adapt_color(&self, color: Color) -> Option<Color> {
+        match self.depth {
+            ColorDepth::TrueColor => Some(color),
+            ColorDepth::Ansi256 => match color {
+                Color::Rgb(r, g, b) => Some(Color::Indexed(to_ansi256((r, g, b)))),
+                color => Some(color),
+            },
+            ColorDepth::Ansi16 => match color {
+                Color::Rgb(..) | Color::Indexed(_) => to_rgb(color).map(to_ansi16),
+                color => Some(color),
+            },
+            ColorDepth::Monochrome => None,
+        }
+    }
+}
+
+// Parses a CSS color: a named color, a hex color, rgb(), rgba(), hsl() or
+// hsla(). The basic color keywords, like `red` or `navy`, map to the
+// terminal's own palette, the rest to exact colors.
+pub fn parse_color(value: &str) -> Option<Color> {
+    let value = value.trim().to_ascii_lowercase();
+
+    if let Some(hex) = value.strip_prefix('#') {
+        return parse_hex(hex);
+    }
+    if let Some(args) = value.strip_prefix("rgba(").or_else(|| value.strip_prefix("rgb(")) {
+        return parse_rgb(args.strip_suffix(')')?);
+    }
+    if let Some(args) = value.strip_prefix("hsla(").or_else(|| value.strip_prefix("hsl(")) {
+        return parse_hsl(args.strip_suffix(')')?);
+    }
+
+    named_color(&value)
+}
+
+fn parse_hex(hex: &str) -> Option<Color> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    // Alpha, in the 4 and 8 digit forms, is ignored.
+    let (r, g, b) = match hex.len() {
+        3 | 4 => {
+            let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).map(|d| d * 17);
+            (digit(0), digit(1), digit(2))
+        },
+        6 | 8 => {
+            let byte = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+            (byte(0), byte(2), byte(4))
+        },
+        _ => return None,
+    };
+
+    Some(Color::Rgb(r.ok()?, g.ok()?, b.ok()?))
+}
+
+// The arguments of rgb(), either `255, 0, 0` or `255 0 0 / 50%`, with each
+// channel a number or a percentage. Alpha is ignored.
+fn parse_rgb(args: &str) -> Option<Color> {
+    let mut args = arguments(args);
+
+    let mut channel = || -> Option<u8> {
+        let channel = args.next()?;
+        let value = match channel.strip_suffix('%') {
+            Some(percent) => percent.parse::<f32>().ok()? * 2.55,
+            None => channel.parse::<f32>().ok()?,
+        };
+        Some(value.round().clamp(0.0, 255.0) as u8)
+    };
+
+    Some(Color::Rgb(channel()?, channel()?, channel()?))
+}
+
+// The arguments of hsl(), with the hue in degrees unless it says turn or
+// rad. Alpha is ignored.
+fn parse_hsl(args: &str) -> Option<Color> {
+    let mut args = arguments(args);
+
+    let hue = args.next()?;
+    let hue = match (hue.strip_suffix("turn"), hue.strip_suffix("rad")) {
+        (Some(turns), _) => turns.parse::<f32>().ok()? * 360.0,
+        (_, Some(radians)) => radians.parse::<f32>().ok()?.to_degrees(),
+        _ => hue.strip_suffix("deg").unwrap_or(hue).parse::<f32>().ok()?,
+    };
+    let mut percent = || -> Option<f32> {
+        let value = args.next()?;
+        Some((value.strip_suffix('%').unwrap_or(value).parse::<f32>().ok()? / 100.0).clamp(0.0, 1.0))
+    };
+    let (saturation, lightness) = (percent()?, percent()?);
+
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let hue = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u8 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+    let channel = |value: f32| ((value + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+
+    Some(Color::Rgb(channel(r), channel(g), channel(b)))
+}
+
+// The channels of a color function, separated by commas or spaces, up to
+// the `/` before alpha.
+fn arguments(args: &str) -> impl Iterator<Item = &str> {
+    args.split('/').next().unwrap_or_default()
+        .split(|c: char| c == ',' || c.is_ascii_whitespace())
+        .filter(|arg| !arg.is_empty())
+}
+
+// The RGB value of a color, as xterm shows it. None for Reset.
+fn to_rgb(color: Color) -> Option<(u8, u8, u8)> {
+    let rgb = match color {
+        Color::Reset => return None,
+        Color::Rgb(r, g, b) => (r, g, b),
+        Color::Indexed(index @ 0..=15) => ANSI16[index as usize].1,
+        Color::Indexed(index @ 16..=231) => {
+            let index = index - 16;
+            (CUBE[(index / 36) as usize], CUBE[(index / 6 % 6) as usize], CUBE[(index % 6) as usize])
+        },
+        Color::Indexed(index) => {
+            let level = 8 + 10 * (index - 232);
+            (level, level, level)
+        },
+        color => ANSI16.iter().find(|(ansi, _)| *ansi == color)?.1,
+    };
+    Some(rgb)
+}
+
+// The levels of each channel in the 6x6x6 color cube of indexes 16 to 231.
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+const ANSI16: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::Red, (128, 0, 0)),
+    (Color::Green, (0, 128, 0)),
+    (Color::Yellow, (128, 128, 0)),
+    (Color::Blue, (0, 0, 128)),
+    (Color::Magenta, (128, 0, 128)),
+    (Color::Cyan, (0, 128, 128)),
+    (Color::Gray, (192, 192, 192)),
+    (Color::DarkGray, (128, 128, 128)),
+    (Color::LightRed, (255, 0, 0)),
+    (Color::LightGreen, (0, 255, 0)),
+    (Color::LightYellow, (255, 255, 0)),
+    (Color::LightBlue, (0, 0, 255)),
+    (Color::LightMagenta, (255, 0, 255)),
+    (Color::LightCyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+fn to_ansi256(rgb: (u8, u8, u8)) -> u8 {
+    let level = |value: u8| CUBE.iter().enumerate().min_by_key(|(_, level)| level.abs_diff(value)).map_or(0, |(i, _)| i as u8);
+    let (r, g, b) = (level(rgb.0), level(rgb.1), level(rgb.2));
+    let cube = (CUBE[r as usize], CUBE[g as usize], CUBE[b as usize]);
+
+    let average = (rgb.0 as u16 + rgb.1 as u16 + rgb.2 as u16) / 3;
+    let gray = (average.saturating_sub(3) / 10).min(23) as u8;
+    let gray_level = 8 + 10 * gray;
+
+    match distance(rgb, (gray_level, gray_level, gray_level)) < distance(rgb, cube) {
+        true => 232 + gray,
+        false => 16 + 36 * r + 6 * g + b,
+    }
+}
+
+fn to_ansi16(rgb: (u8, u8, u8)) -> Color {
+    ANSI16.iter().min_by_key(|(_, ansi)| distance(rgb, *ansi)).map_or(Color::Reset, |(color, _)| *color)
+}
+
+// The "redmean" approximation of how different two colors look.
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let mean = (a.0 as i32 + b.0 as i32) / 2;
+    let (dr, dg, db) = (a.0 as i32 - b.0 as i32, a.1 as i32 - b.1 as i32, a.2 as i32 - b.2 as i32);
+    ((((512 + mean) * dr * dr) >> 8) + 4 * dg * dg + (((767 - mean) * db * db) >> 8)) as u32
+}
+
+// `fg`, mixed toward white or black until it stands out from `behind`.
+// Colors from the terminal's palette, which could be anything, are left as
+// they are.
+fn with_contrast(fg: Color, behind: (u8, u8, u8)) -> Color {
+    let Color::Rgb(r, g, b) = fg else {
+        return fg;
+    };
+    if contrast((r, g, b), behind) >= MIN_CONTRAST {
+        return fg;
+    }
+
+    let target: f32 = match luminance(behind) < 0.18 {
+        true => 255.0,
+        false => 0.0,
+    };
+    for step in 1..=10 {
+        let mix = |channel: u8| (channel as f32 + (target - channel as f32) * step as f32 / 10.0).round() as u8;
+        let mixed = (mix(r), mix(g), mix(b));
+        if contrast(mixed, behind) >= MIN_CONTRAST {
+            return Color::Rgb(mixed.0, mixed.1, mixed.2);
+        }
+    }
+
+    fg
+}
+
+// The WCAG contrast ratio, from 1 to 21.
+fn contrast(a: (u8, u8, u8), b: (u8, u8, u8)) -> f32 {
+    let (a, b) = (luminance(a), luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+fn luminance((r, g, b): (u8, u8, u8)) -> f32 {
+    let linear = |channel: u8| {
+        let channel = channel as f32 / 255.0;
+        match channel <= 0.03928 {
+            true => channel / 12.92,
+            false => ((channel + 0.055) / 1.055).powf(2.4),
+        }
+    };
+    0.2126 * linear(r) + 0.7152 * linear(g) + 0.0722 * linear(b)
+}
+
+fn named_color(name: &str) -> Option<Color> {
+    let basic = match name {
+        "black" => Some(Color::Black),
+        "maroon" => Some(Color::Red),
+        "green" => Some(Color::Green),
+        "olive" => Some(Color::Yellow),
+        "navy" => Some(Color::Blue),
+        "purple" => Some(Color::Magenta),
+        "teal" => Some(Color::Cyan),
+        "silver" => Some(Color::Gray),
+        "gray" | "grey" => Some(Color::DarkGray),
+        "red" => Some(Color::LightRed),
+        "lime" => Some(Color::LightGreen),
+        "yellow" => Some(Color::LightYellow),
+        "blue" => Some(Color::LightBlue),
+        "fuchsia" | "magenta" => Some(Color::LightMagenta),
+        "aqua" | "cyan" => Some(Color::LightCyan),
+        "white" => Some(Color::White),
+        _ => None,
+    };
+    if basic.is_some() {
+        return basic;
+    }
+
+    let rgb: u32 = match name {
+        "aliceblue" => 0xf0f8ff,
+        "antiquewhite" => 0xfaebd7,
+        "aquamarine" => 0x7fffd4,
+        "azure" => 0xf0ffff,
+        "beige" => 0xf5f5dc,
+        "bisque" => 0xffe4c4,
+        "blanchedalmond" => 0xffebcd,
+        "blueviolet" => 0x8a2be2,
+        "brown" => 0xa52a2a,
+        "burlywood" => 0xdeb887,
+        "cadetblue" => 0x5f9ea0,
+        "chartreuse" => 0x7fff00,
+        "chocolate" => 0xd2691e,
+        "coral" => 0xff7f50,
+        "cornflowerblue" => 0x6495ed,
+        "cornsilk" => 0xfff8dc,
+        "crimson" => 0xdc143c,
+        "darkblue" => 0x00008b,
+        "darkcyan" => 0x008b8b,
+        "darkgoldenrod" => 0xb8860b,
+        "darkgray" | "darkgrey" => 0xa9a9a9,
+        "darkgreen" => 0x006400,
+        "darkkhaki" => 0xbdb76b,
+        "darkmagenta" => 0x8b008b,
+        "darkolivegreen" => 0x556b2f,
+        "darkorange" => 0xff8c00,
+        "darkorchid" => 0x9932cc,
+        "darkred" => 0x8b0000,
+        "darksalmon" => 0xe9967a,
+        "darkseagreen" => 0x8fbc8f,
+        "darkslateblue" => 0x483d8b,
+        "darkslategray" | "darkslategrey" => 0x2f4f4f,
+        "darkturquoise" => 0x00ced1,
+        "darkviolet" => 0x9400d3,
+        "deeppink" => 0xff1493,
+        "deepskyblue" => 0x00bfff,
+        "dimgray" | "dimgrey" => 0x696969,
+        "dodgerblue" => 0x1e90ff,
+        "firebrick" => 0xb22222,
+        "floralwhite" => 0xfffaf0,
+        "forestgreen" => 0x228b22,
+        "gainsboro" => 0xdcdcdc,
+        "ghostwhite" => 0xf8f8ff,
+        "gold" => 0xffd700,
+        "goldenrod" => 0xdaa520,
+        "greenyellow" => 0xadff2f,
+        "honeydew" => 0xf0fff0,
+        "hotpink" => 0xff69b4,
+        "indianred" => 0xcd5c5c,
+        "indigo" => 0x4b0082,
+        "ivory" => 0xfffff0,
+        "khaki" => 0xf0e68c,
+        "lavender" => 0xe6e6fa,
+        "lavenderblush" => 0xfff0f5,
+        "lawngreen" => 0x7cfc00,
+        "lemonchiffon" => 0xfffacd,
+        "lightblue" => 0xadd8e6,
+        "lightcoral" => 0xf08080,
+        "lightcyan" => 0xe0ffff,
+        "lightgoldenrodyellow" => 0xfafad2,
+        "lightgray" | "lightgrey" => 0xd3d3d3,
+        "lightgreen" => 0x90ee90,
+        "lightpink" => 0xffb6c1,
+        "lightsalmon" => 0xffa07a,
+        "lightseagreen" => 0x20b2aa,
+        "lightskyblue" => 0x87cefa,
+        "lightslategray" | "lightslategrey" => 0x778899,
+        "lightsteelblue" => 0xb0c4de,
+        "lightyellow" => 0xffffe0,
+        "limegreen" => 0x32cd32,
+        "linen" => 0xfaf0e6,
+        "mediumaquamarine" => 0x66cdaa,
+        "mediumblue" => 0x0000cd,
+        "mediumorchid" => 0xba55d3,
+        "mediumpurple" => 0x9370db,
+        "mediumseagreen" => 0x3cb371,
+        "mediumslateblue" => 0x7b68ee,
+        "mediumspringgreen" => 0x00fa9a,
+        "mediumturquoise" => 0x48d1cc,
+        "mediumvioletred" => 0xc71585,
+        "midnightblue" => 0x191970,
+        "mintcream" => 0xf5fffa,
+        "mistyrose" => 0xffe4e1,
+        "moccasin" => 0xffe4b5,
+        "navajowhite" => 0xffdead,
+        "oldlace" => 0xfdf5e6,
+        "olivedrab" => 0x6b8e23,
+        "orange" => 0xffa500,
+        "orangered" => 0xff4500,
+        "orchid" => 0xda70d6,
+        "palegoldenrod" => 0xeee8aa,
+        "palegreen" => 0x98fb98,
+        "paleturquoise" => 0xafeeee,
+        "palevioletred" => 0xdb7093,
+        "papayawhip" => 0xffefd5,
+        "peachpuff" => 0xffdab9,
+        "peru" => 0xcd853f,
+        "pink" => 0xffc0cb,
+        "plum" => 0xdda0dd,
+        "powderblue" => 0xb0e0e6,
+        "rebeccapurple" => 0x663399,
+        "rosybrown" => 0xbc8f8f,
+        "royalblue" => 0x4169e1,
+        "saddlebrown" => 0x8b4513,
+        "salmon" => 0xfa8072,
+        "sandybrown" => 0xf4a460,
+        "seagreen" => 0x2e8b57,
+        "seashell" => 0xfff5ee,
+        "sienna" => 0xa0522d,
+        "skyblue" => 0x87ceeb,
+        "slateblue" => 0x6a5acd,
+        "slategray" | "slategrey" => 0x708090,
+        "snow" => 0xfffafa,
+        "springgreen" => 0x00ff7f,
+        "steelblue" => 0x4682b4,
+        "tan" => 0xd2b48c,
+        "thistle" => 0xd8bfd8,
+        "tomato" => 0xff6347,
+        "turquoise" => 0x40e0d0,
+        "violet" => 0xee82ee,
+        "wheat" => 0xf5deb3,
+        "whitesmoke" => 0xf5f5f5,
+        "yellowgreen" => 0x9acd32,
+        _ => return None,
+    };
+
+    Some(Color::from_u32(rgb))
+}
+
+#[cfg(test)]
+mod test {
+    use ratatui::style::{Color, Style};
+
+    use crate::tuihtml::color::{parse_color, Background, ColorDepth, Palette};
+
+    #[test]
+    fn colors_are_parsed_in_every_form() {
+        assert_eq!(parse_color("#FA0"), Some(Color::Rgb(0xff, 0xaa, 0x00)));
+        assert_eq!(parse_color("#1a2b3c80"), Some(Color::Rgb(0x1a, 0x2b, 0x3c)));
+        assert_eq!(parse_color(" rgba(10, 20, 30, 0.5) "), Some(Color::Rgb(10, 20, 30)));
+        assert_eq!(parse_color("rgb(100% 50% 0% / 20%)"), Some(Color::Rgb(255, 128, 0)));
+        assert_eq!(parse_color("hsl(0, 100%, 50%)"), Some(Color::Rgb(255, 0, 0)));
+        assert_eq!(parse_color("hsla(0.5turn 100% 25% / 0.1)"), Some(Color::Rgb(0, 128, 128)));
+        assert_eq!(parse_color("hsl(-120deg, 50%, 50%)"), Some(Color::Rgb(64, 64, 191)));
+        assert_eq!(parse_color("Navy"), Some(Color::Blue));
+        assert_eq!(parse_color("RebeccaPurple"), Some(Color::Rgb(0x66, 0x33, 0x99)));
+        assert_eq!(parse_color("#12345"), None);
+        assert_eq!(parse_color("transparent"), None);
+        assert_eq!(parse_color("rgb(1, 2)"), None);
+    }
+
+    #[test]
+    fn colors_are_downsampled_to_the_terminal() {
+        let palette = |depth| Palette { depth, background: None };
+
+        assert_eq!(palette(ColorDepth::TrueColor).adapt_color(Color::Rgb(1, 2, 3)), Some(Color::Rgb(1, 2, 3)));
+        assert_eq!(palette(ColorDepth::Ansi256).adapt_color(Color::Rgb(255, 135, 0)), Some(Color::Indexed(208)));
+        assert_eq!(palette(ColorDepth::Ansi256).adapt_color(Color::Rgb(0x80, 0x80, 0x80)), Some(Color::Indexed(244)));
+        assert_eq!(palette(ColorDepth::Ansi256).adapt_color(Color::Cyan), Some(Color::Cyan));
+        assert_eq!(palette(ColorDepth::Ansi16).adapt_color(Color::Rgb(250, 10, 20)), Some(Color::LightRed));
+        assert_eq!(palette(ColorDepth::Ansi16).adapt_color(Color::Indexed(17)), Some(Color::Blue));
+        assert_eq!(palette(ColorDepth::Ansi16).adapt_color(Color::Indexed(9)), Some(Color::LightRed));
+        assert_eq!(palette(ColorDepth::Monochrome).adapt_color(Color::White), None);
+
+        // Too dark to read on a dark background, or too light on a light one.
+        let dark = Palette { depth: ColorDepth::TrueColor, background: Some(Background::Dark) };
+        let light = Palette { depth: ColorDepth::TrueColor, background: Some(Background::Light) };
+        assert_eq!(dark.adapt(Style::new().fg(Color::Rgb(0x10, 0x10, 0x40))), Style::new().fg(Color::Rgb(0x88, 0x88, 0xa0)));
+        assert_eq!(dark.adapt(Style::new().fg(Color::Rgb(0xee, 0xee, 0xee))), Style::new().fg(Color::Rgb(0xee, 0xee, 0xee)));
+        assert_eq!(light.adapt(Style::new().fg(Color::Rgb(0xff, 0xff, 0x00))), Style::new().fg(Color::Rgb(0x66, 0x66, 0x00)));
+        // The text's own background counts over the terminal's.
+        assert_eq!(light.adapt(Style::new().fg(Color::Rgb(0xff, 0xff, 0xff)).bg(Color::Black)), Style::new().fg(Color::Rgb(0xff, 0xff, 0xff)).bg(Color::Black));
+    }
+
+    #[test]
+    fn the_environment_sets_the_color_depth() {
+        let depth = |vars: &[(&str, &str)]| ColorDepth::from_env(|name| {
+            vars.iter().find(|(var, _)| *var == name).map(|(_, value)| value.to_string())
+        });
+
+        assert_eq!(depth(&[("NO_COLOR", "1"), ("COLORTERM", "truecolor"), ("TERM", "xterm-256color")]), ColorDepth::Monochrome);
+        assert_eq!(depth(&[("NO_COLOR", ""), ("COLORTERM", "24bit")]), ColorDepth::TrueColor);
+        assert_eq!(depth(&[("TERM", "screen-256color")]), ColorDepth::Ansi256);
+        assert_eq!(depth(&[("TERM", "xterm")]), ColorDepth::Ansi16);
+        assert_eq!(depth(&[("TERM", "dumb")]), ColorDepth::Monochrome);
+        assert_eq!(depth(&[]), ColorDepth::Monochrome);
+
+        assert_eq!(Background::from_colorfgbg("15;0"), Some(Background::Dark));
+        assert_eq!(Background::from_colorfgbg("0;default;15"), Some(Background::Light));
+        assert_eq!(Background::from_colorfgbg("default"), None);
+    }
+}
@@ -0,0 +1,294 @@
+use ratatui::style::{Modifier, Style};
+
+use crate::tuihtml::{color::parse_color, dom::{Document, NodeId}, html::HtmlTag, selector::{Selector, Specificity}};
+
+// What CSS says about an element that a terminal can show.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InlineStyle {
+    pub style: Style,
+    // display: none, the element and everything in it are left out.
+    pub display_none: bool,
+    // visibility, Some(false) when hidden. Hidden text still takes up its
+    // space, as blanks.
+    pub visible: Option<bool>,
+}
+
+// The rules of <style> elements, kept for the properties InlineStyle covers.
+// At-rules, like @media, are skipped.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StyleSheet {
+    rules: Vec<StyleRule>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct StyleRule {
+    // Selectors that can't be supported are left out, a rule left with none
+    // is dropped.
+    selectors: Vec<Selector>,
+    declarations: Vec<Declaration>,
+}
+
+// Where a declaration stands in the cascade: whether it is !important,
+// whether it comes from a style attribute, its rule's specificity and the
+// rule's place in the sheet.
+type CascadeOrder = (bool, bool, Specificity, usize);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Declaration {
+    property: String,
+    value: String,
+    important: bool,
+}
+
+impl StyleSheet {
+    pub fn parse(css: &str) -> Self {
+        let css = strip_comments(css);
+        let mut rules = Vec::new();
+        let mut rest = css.as_str();
+
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() {
+                break;
+            }
+
+            if rest.starts_with('@') {
+                // A block, like @media has, or a statement, like @import.
+                rest = match (rest.find('{'), rest.find(';')) {
+                    (Some(open), semicolon) if semicolon.is_none_or(|semicolon| open < semicolon) => &rest[block_end(rest, open)..],
+                    (_, Some(semicolon)) => &rest[semicolon + 1..],
+                    _ => break,
+                };
+                continue;
+            }
+
+            let Some(open) = rest.find('{') else {
+                break;
+            };
+            let close = rest[open..].find('}').map_or(rest.len(), |close| open + close);
+
+            let selectors: Vec<Selector> = split_selectors(&rest[..open]).filter_map(Selector::parse).collect();
+            if !selectors.is_empty() {
+                rules.push(StyleRule { selectors, declarations: declarations(&rest[open + 1..close]).collect() });
+            }
+
+            rest = rest.get(close + 1..).unwrap_or_default();
+        }
+
+        StyleSheet { rules }
+    }
+
+    // Adds the rules of `other` after these, so they win ties.
+    pub fn append(&mut self, other: StyleSheet) {
+        self.rules.extend(other.rules);
+    }
+
+    // The style of node `id` from the rules that match it and its style
+    // attribute. Declarations are applied in cascade order: !important
+    // after the rest, then the style attribute after the rules, then by
+    // specificity and last by order. Old attributes like <font color> come
+    // before all of them.
+    pub fn compute(&self, document: &Document, id: NodeId) -> InlineStyle {
+        let attribute: Vec<Declaration> = document.attr(id, "style").map(|style| declarations(style).collect()).unwrap_or_default();
+        let hints = presentational_hints(document, id);
+
+        let mut matched: Vec<(CascadeOrder, &Declaration)> = hints.iter().map(|hint| ((false, false, (0, 0, 0), 0), hint)).collect();
+        for (order, rule) in self.rules.iter().enumerate() {
+            let Some(specificity) = rule.selectors.iter()
+                .filter(|selector| selector.matches(document, id))
+                .map(Selector::specificity)
+                .max() else {
+                continue;
+            };
+
+            matched.extend(rule.declarations.iter().map(|declaration| ((declaration.important, false, specificity, order), declaration)));
+        }
+        matched.extend(attribute.iter().map(|declaration| ((declaration.important, true, (0, 0, 0), 0), declaration)));
+        matched.sort_by_key(|(key, _)| *key);
+
+        let mut computed = InlineStyle::default();
+        for (_, declaration) in matched {
+            computed.apply(declaration);
+        }
+        computed
+    }
+}
+
+// Parses the declarations of a style attribute, like
+// `color: #c00; font-weight: bold`. Properties and values that can't be
+// shown are ignored.
+#[cfg(test)]
+pub(crate) fn parse_inline_style(css: &str) -> InlineStyle {
+    let mut inline = InlineStyle::default();
+    for declaration in declarations(css) {
+        inline.apply(&declaration);
+    }
+    inline
+}
+
+impl InlineStyle {
+    pub(crate) fn apply(&mut self, declaration: &Declaration) {
+        let Declaration { property, value, .. } = declaration;
+        let inline = self;
+        let style = inline.style;
+
+        match property.as_str() {
+            "color" => if let Some(color) = parse_color(value) {
+                inline.style = style.fg(color);
+            },
+            "background-color" | "background" => if let Some(color) = parse_color(value) {
+                inline.style = style.bg(color);
+            },
+            "font-weight" => match value.as_str() {
+                "bold" | "bolder" => inline.style = style.add_modifier(Modifier::BOLD),
+                "normal" | "lighter" => inline.style = style.remove_modifier(Modifier::BOLD),
+                weight => if let Ok(weight) = weight.parse::<u16>() {
+                    inline.style = match weight >= 600 {
+                        true => style.add_modifier(Modifier::BOLD),
+                        false => style.remove_modifier(Modifier::BOLD),
+                    };
+                },
+            },
+            "font-style" => match value.as_str() {
+                "italic" | "oblique" => inline.style = style.add_modifier(Modifier::ITALIC),
+                "normal" => inline.style = style.remove_modifier(Modifier::ITALIC),
+                _ => {}
+            },
+            "text-decoration" | "text-decoration-line" => {
+                for keyword in value.split_ascii_whitespace() {
+                    inline.style = match keyword {
+                        "underline" => inline.style.add_modifier(Modifier::UNDERLINED),
+                        "line-through" => inline.style.add_modifier(Modifier::CROSSED_OUT),
+                        "none" => inline.style.remove_modifier(Modifier::UNDERLINED | Modifier::CROSSED_OUT),
+                        _ => inline.style,
+                    };
+                }
+            },
+            "display" => inline.display_none = value == "none",
+            "visibility" => match value.as_str() {
+                "hidden" | "collapse" => inline.visible = Some(false),
+                "visible" => inline.visible = Some(true),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+// Declarations for the attributes that styled pages before CSS did.
+fn presentational_hints(document: &Document, id: NodeId) -> Vec<Declaration> {
+    let Some(element) = document.node(id).element() else {
+        return Vec::new();
+    };
+    let hint = |property: &str, value: Option<&str>| {
+        let value = value?.trim().to_ascii_lowercase();
+        // These also take hex without the #, like `ff0000`.
+        let value = match (value.len(), value.chars().all(|c| c.is_ascii_hexdigit())) {
+            (3 | 6, true) => format!("#{}", value),
+            _ => value,
+        };
+        Some(Declaration { property: property.to_owned(), value, important: false })
+    };
+
+    let color = match element.tag {
+        HtmlTag::FONT => hint("color", element.attr("color")),
+        _ => None,
+    };
+    color.into_iter().chain(hint("background-color", element.attr("bgcolor"))).collect()
+}
+
+// Splits declarations into lowercased properties and values.
+pub(crate) fn declarations(css: &str) -> impl Iterator<Item = Declaration> + '_ {
+    css.split(';').filter_map(|declaration| {
+        let (property, value) = declaration.split_once(':')?;
+        let value = value.trim().to_ascii_lowercase();
+        let (value, important) = match value.strip_suffix("!important") {
+            Some(value) => (value.trim_end(), true),
+            None => (value.as_str(), false),
+        };
+
+        match property.trim().is_empty() || value.is_empty() {
+            true => None,
+            false => Some(Declaration { property: property.trim().to_ascii_lowercase(), value: value.to_owned(), important }),
+        }
+    })
+}
+
+fn strip_comments(css: &str) -> String {
+    let mut stripped = String::with_capacity(css.len());
+    let mut rest = css;
+
+    while let Some(start) = rest.find("/*") {
+        stripped.push_str(&rest[..start]);
+        rest = rest[start + 2..].find("*/").map_or("", |end| &rest[start + 2 + end + 2..]);
+    }
+    stripped.push_str(rest);
+
+    stripped
+}
+
+// Where the block opening at `open` ends, past its closing brace.
+fn block_end(css: &str, open: usize) -> usize {
+    let mut depth = 0;
+
+    for (i, c) in css[open..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return open + i + 1;
+                }
+            },
+            _ => {}
+        }
+    }
+
+    css.len()
+}
+
+// Splits a selector list at the commas outside of brackets and quotes.
+fn split_selectors(selectors: &str) -> impl Iterator<Item = &str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut bracket = false;
+    let mut quote = None;
+
+    for (i, c) in selectors.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {},
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '[') => bracket = true,
+            (None, ']') => bracket = false,
+            (None, ',') if !bracket => {
+                parts.push(&selectors[start..i]);
+                start = i + 1;
+            },
+            _ => {}
+        }
+    }
+    parts.push(&selectors[start..]);
+
+    parts.into_iter()
+}
+
+#[cfg(test)]
+mod test {
+    use ratatui::style::{Color, Modifier, Style};
+
+    use crate::tuihtml::css::{parse_inline_style, InlineStyle};
+
+    #[test]
+    fn inline_styles_keep_what_a_terminal_can_show() {
+        let inline = parse_inline_style("COLOR: Red; font-family: serif; font-style: oblique;; text-decoration: none; background: url(a.png); visibility: hidden; margin:");
+        assert_eq!(inline, InlineStyle {
+            style: Style::new().fg(Color::LightRed).add_modifier(Modifier::ITALIC).remove_modifier(Modifier::UNDERLINED | Modifier::CROSSED_OUT),
+            display_none: false,
+            visible: Some(false),
+        });
+
+        assert!(parse_inline_style("display: none !IMPORTANT").display_none);
+        assert_eq!(parse_inline_style("font-weight: 400").style, Style::new().remove_modifier(Modifier::BOLD));
+    }
+}
@@ -0,0 +1,77 @@
+use std::fmt;
+
+use crate::tuihtml::source::SourceSpan;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ParseErrorKind {
+    // The input ended inside a tag, before its '>'.
+    EofInTag,
+    // The input ended inside a quoted attribute value.
+    UnclosedQuote,
+    EofInComment,
+    EofInDoctype,
+    EofInCData,
+    DuplicateAttribute,
+    EndTagWithAttributes,
+    // An end tag with no open element to close, which is ignored.
+    StrayEndTag,
+    // A tag that closed, or had to work around, elements that were
+    // still open.
+    MisnestedTag,
+    // An element that requires an end tag was still open at the end of
+    // the input.
+    UnclosedElement,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub message: String,
+    pub span: SourceSpan,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, message: String, span: SourceSpan) -> Self {
+        ParseError {
+            kind,
+            message,
+            span,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.span.start.line, self.span.start.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// Collects parse errors when enabled and silently drops them otherwise, so
+// the tokenizer and tree builder can report unconditionally without paying
+// for it in the default mode.
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    enabled: bool,
+    errors: Vec<ParseError>,
+}
+
+impl Diagnostics {
+    pub fn new(enabled: bool) -> Self {
+        Diagnostics {
+            enabled,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn report(&mut self, kind: ParseErrorKind, span: SourceSpan, message: impl FnOnce() -> String) {
+        if self.enabled {
+            self.errors.push(ParseError::new(kind, message(), span));
+        }
+    }
+
+    pub fn take(&mut self) -> Vec<ParseError> {
+        std::mem::take(&mut self.errors)
+    }
+}
@@ -0,0 +1,395 @@
+use crate::tuihtml::{html::{HtmlElement, HtmlTag}, source::{SourcePosition, SourceSpan}};
+
+// A node's place in the arena of the Document it came from, meaningless in
+// any other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(usize);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeData {
+    Document,
+    Element(HtmlElement),
+    Text(String),
+    Comment(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub data: NodeData,
+    // For elements this runs from the start tag to the end tag, or to
+    // wherever the element was implicitly closed.
+    pub span: SourceSpan,
+    pub parent: Option<NodeId>,
+    pub first_child: Option<NodeId>,
+    pub last_child: Option<NodeId>,
+    pub prev_sibling: Option<NodeId>,
+    pub next_sibling: Option<NodeId>,
+}
+
+impl Node {
+    fn new(data: NodeData, span: SourceSpan) -> Self {
+        Node {
+            data,
+            span,
+            parent: None,
+            first_child: None,
+            last_child: None,
+            prev_sibling: None,
+            next_sibling: None,
+        }
+    }
+
+    pub fn element(&self) -> Option<&HtmlElement> {
+        match &self.data {
+            NodeData::Element(element) => Some(element),
+            _ => None,
+        }
+    }
+
+    pub fn tag(&self) -> Option<&HtmlTag> {
+        self.element().map(|element| &element.tag)
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        match &self.data {
+            NodeData::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.element().and_then(|element| element.attr(name))
+    }
+}
+
+// Nodes live in a flat arena and refer to each other by index, which keeps
+// the tree cheap to clone and lets callers hold on to a `NodeId` while
+// walking it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Document {
+    nodes: Vec<Node>,
+    doctype: Option<String>,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Document {
+    pub fn new() -> Self {
+        Document {
+            nodes: vec![Node::new(NodeData::Document, SourceSpan::default())],
+            doctype: None,
+        }
+    }
+
+    pub fn doctype(&self) -> Option<&str> {
+        self.doctype.as_deref()
+    }
+
+    pub(crate) fn set_doctype(&mut self, doctype: String) {
+        self.doctype = Some(doctype);
+    }
+
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    // How many nodes are in the tree below the root. This walks the tree.
+    pub fn len(&self) -> usize {
+        self.descendants(self.root()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.first_child(self.root()).is_none()
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    pub fn span(&self, id: NodeId) -> SourceSpan {
+        self.nodes[id.0].span
+    }
+
+    pub(crate) fn set_span_end(&mut self, id: NodeId, end: SourcePosition) {
+        self.nodes[id.0].span.end = end;
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id.0].parent
+    }
+
+    pub fn first_child(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id.0].first_child
+    }
+
+    pub fn last_child(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id.0].last_child
+    }
+
+    pub fn next_sibling(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id.0].next_sibling
+    }
+
+    pub fn prev_sibling(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id.0].prev_sibling
+    }
+
+    pub fn children(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        Children {
+            document: self,
+            next: self.nodes[id.0].first_child,
+        }
+    }
+
+    pub fn ancestors(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        Ancestors {
+            document: self,
+            next: self.nodes[id.0].parent,
+        }
+    }
+
+    pub fn traverse(&self, id: NodeId) -> impl Iterator<Item = NodeEdge> + '_ {
+        Traverse {
+            document: self,
+            root: id,
+            next: Some(NodeEdge::Start(id)),
+        }
+    }
+
+    // Carries on a walk of the subtree at `root` from `edge`, which is
+    // yielded first.
+    pub fn traverse_from(&self, root: NodeId, edge: NodeEdge) -> impl Iterator<Item = NodeEdge> + '_ {
+        Traverse {
+            document: self,
+            root,
+            next: Some(edge),
+        }
+    }
+
+    // Every node below `id` in document order, not including `id` itself.
+    pub fn descendants(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        self.traverse(id)
+            .filter_map(|edge| match edge {
+                NodeEdge::Start(node) => Some(node),
+                NodeEdge::End(_) => None,
+            })
+            .skip(1)
+    }
+
+    pub fn find(&self, tag: HtmlTag) -> Option<NodeId> {
+        self.find_all(tag).next()
+    }
+
+    pub fn find_all(&self, tag: HtmlTag) -> impl Iterator<Item = NodeId> + '_ {
+        self.descendants(self.root())
+            .filter(move |id| self.nodes[id.0].tag() == Some(&tag))
+    }
+
+    pub fn attr(&self, id: NodeId, name: &str) -> Option<&str> {
+        self.nodes[id.0].attr(name)
+    }
+
+    pub fn text_content(&self, id: NodeId) -> String {
+        std::iter::once(id)
+            .chain(self.descendants(id))
+            .filter_map(|node| self.nodes[node.0].text())
+            .collect()
+    }
+
+    pub(crate) fn append(&mut self, parent: NodeId, data: NodeData, span: SourceSpan) -> NodeId {
+        let id = self.create(data, span);
+        self.append_child(parent, id);
+        id
+    }
+
+    // Creates a node that is not yet attached anywhere in the tree.
+    pub(crate) fn create(&mut self, data: NodeData, span: SourceSpan) -> NodeId {
+        self.nodes.push(Node::new(data, span));
+        NodeId(self.nodes.len() - 1)
+    }
+
+    // Attaches `child` as the last child of `parent`, detaching it from its
+    // current position first.
+    pub(crate) fn append_child(&mut self, parent: NodeId, child: NodeId) {
+        self.detach(child);
+
+        self.nodes[child.0].parent = Some(parent);
+        self.nodes[child.0].prev_sibling = self.nodes[parent.0].last_child;
+
+        match self.nodes[parent.0].last_child {
+            Some(last) => self.nodes[last.0].next_sibling = Some(child),
+            None => self.nodes[parent.0].first_child = Some(child),
+        }
+        self.nodes[parent.0].last_child = Some(child);
+    }
+
+    pub(crate) fn detach(&mut self, id: NodeId) {
+        let Some(parent) = self.nodes[id.0].parent.take() else {
+            return;
+        };
+        let prev = self.nodes[id.0].prev_sibling.take();
+        let next = self.nodes[id.0].next_sibling.take();
+
+        match prev {
+            Some(prev) => self.nodes[prev.0].next_sibling = next,
+            None => self.nodes[parent.0].first_child = next,
+        }
+        match next {
+            Some(next) => self.nodes[next.0].prev_sibling = prev,
+            None => self.nodes[parent.0].last_child = prev,
+        }
+    }
+
+    pub(crate) fn move_children(&mut self, from: NodeId, to: NodeId) {
+        while let Some(child) = self.nodes[from.0].first_child {
+            self.append_child(to, child);
+        }
+    }
+
+    // Adjacent text is merged into a single node, the way a browser DOM
+    // never holds two text siblings in a row after parsing.
+    pub(crate) fn append_text(&mut self, parent: NodeId, text: &str, span: SourceSpan) -> NodeId {
+        if let Some(last) = self.nodes[parent.0].last_child {
+            if let NodeData::Text(existing) = &mut self.nodes[last.0].data {
+                existing.push_str(text);
+                self.nodes[last.0].span = self.nodes[last.0].span.union(&span);
+                return last;
+            }
+        }
+
+        self.append(parent, NodeData::Text(text.to_string()), span)
+    }
+}
+
+struct Children<'a> {
+    document: &'a Document,
+    next: Option<NodeId>,
+}
+
+impl Iterator for Children<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let current = self.next?;
+        self.next = self.document.next_sibling(current);
+        Some(current)
+    }
+}
+
+struct Ancestors<'a> {
+    document: &'a Document,
+    next: Option<NodeId>,
+}
+
+impl Iterator for Ancestors<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let current = self.next?;
+        self.next = self.document.parent(current);
+        Some(current)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeEdge {
+    Start(NodeId),
+    End(NodeId),
+}
+
+// Walks a subtree without recursion, yielding a Start edge when a node is
+// entered and an End edge once all of its children have been visited.
+struct Traverse<'a> {
+    document: &'a Document,
+    root: NodeId,
+    next: Option<NodeEdge>,
+}
+
+impl Iterator for Traverse<'_> {
+    type Item = NodeEdge;
+
+    fn next(&mut self) -> Option<NodeEdge> {
+        let current = self.next?;
+
+        self.next = match current {
+            NodeEdge::Start(id) => match self.document.first_child(id) {
+                Some(child) => Some(NodeEdge::Start(child)),
+                None => Some(NodeEdge::End(id)),
+            },
+            NodeEdge::End(id) if id == self.root => None,
+            NodeEdge::End(id) => match self.document.next_sibling(id) {
+                Some(sibling) => Some(NodeEdge::Start(sibling)),
+                None => self.document.parent(id).map(NodeEdge::End),
+            },
+        };
+
+        Some(current)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tuihtml::{dom::{Document, NodeData, NodeEdge}, html::{Attributes, HtmlElement, HtmlTag}, source::SourceSpan};
+
+    fn element(tag: HtmlTag) -> NodeData {
+        NodeData::Element(HtmlElement::new(tag, Attributes::new(), false, false))
+    }
+
+    #[test]
+    fn traverse_visits_nodes_in_document_order() {
+        let mut document = Document::new();
+        let p = document.append(document.root(), element(HtmlTag::P), SourceSpan::default());
+        let b = document.append(p, element(HtmlTag::BOLD), SourceSpan::default());
+        let text = document.append_text(b, "Hello", SourceSpan::default());
+        let hr = document.append(document.root(), element(HtmlTag::HR), SourceSpan::default());
+
+        assert_eq!(document.traverse(p).collect::<Vec<_>>(), vec![
+            NodeEdge::Start(p),
+            NodeEdge::Start(b),
+            NodeEdge::Start(text),
+            NodeEdge::End(text),
+            NodeEdge::End(b),
+            NodeEdge::End(p),
+        ]);
+        assert_eq!(document.descendants(document.root()).collect::<Vec<_>>(), vec![p, b, text, hr]);
+        assert_eq!(document.next_sibling(p), Some(hr));
+        assert_eq!(document.prev_sibling(hr), Some(p));
+    }
+
+    #[test]
+    fn adjacent_text_is_merged() {
+        let mut document = Document::new();
+        let root = document.root();
+        let first = document.append_text(root, "Hello", SourceSpan::default());
+        let second = document.append_text(root, " World", SourceSpan::default());
+        document.append(root, NodeData::Comment("note".into()), SourceSpan::default());
+        let third = document.append_text(root, "!", SourceSpan::default());
+
+        assert_eq!(first, second);
+        assert_ne!(first, third);
+        assert_eq!(document.text_content(root), "Hello World!");
+    }
+
+    #[test]
+    fn len_and_is_empty_count_the_nodes_in_the_tree() {
+        let mut document = Document::new();
+        assert_eq!(document.len(), 0);
+        assert!(document.is_empty());
+
+        let p = document.append(document.root(), element(HtmlTag::P), SourceSpan::default());
+        document.append_text(p, "Hello", SourceSpan::default());
+        // Nodes taken out of the tree no longer count.
+        let b = document.append(p, element(HtmlTag::BOLD), SourceSpan::default());
+        document.detach(b);
+        assert_eq!(document.len(), 2);
+        assert!(!document.is_empty());
+
+        document.detach(p);
+        assert_eq!(document.len(), 0);
+        assert!(document.is_empty());
+    }
+}
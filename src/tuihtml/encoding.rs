@@ -0,0 +1,242 @@
+use crate::tuihtml::error::{Error, Result};
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252, X_USER_DEFINED};
+
+// How many bytes the <meta> prescan looks at, as in browsers.
+const PRESCAN_LIMIT: usize = 1024;
+
+// Where the encoding of a document came from, strongest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodingSource {
+    Bom,
+    // The caller's hint, typically the charset of an HTTP Content-Type.
+    Hint,
+    Meta,
+    // Nothing said; UTF-8 if the bytes are valid UTF-8, windows-1252
+    // otherwise.
+    Default,
+}
+
+// Picks the encoding of an HTML document following the HTML5 sniffing order:
+// a byte order mark, then `hint`, then a <meta charset> or http-equiv
+// Content-Type near the start of the document.
+pub fn detect_encoding(bytes: &[u8], hint: Option<&str>) -> (&'static Encoding, EncodingSource) {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return (encoding, EncodingSource::Bom);
+    }
+
+    if let Some(encoding) = hint.and_then(|label| Encoding::for_label(label.trim().as_bytes())) {
+        return (encoding, EncodingSource::Hint);
+    }
+
+    if let Some(encoding) = prescan(&bytes[..bytes.len().min(PRESCAN_LIMIT)]) {
+        return (encoding, EncodingSource::Meta);
+    }
+
+    match std::str::from_utf8(bytes) {
+        Ok(_) => (UTF_8, EncodingSource::Default),
+        Err(_) => (WINDOWS_1252, EncodingSource::Default),
+    }
+}
+
+// Decodes a document in the encoding detect_encoding() picks. Malformed
+// sequences become U+FFFD.
+pub fn decode_html(bytes: &[u8], hint: Option<&str>) -> String {
+    let (encoding, _) = detect_encoding(bytes, hint);
+    let (text, _, _) = encoding.decode(bytes);
+    text.into_owned()
+}
+
+// Like decode_html(), but fails instead of replacing malformed sequences.
+pub fn try_decode_html(bytes: &[u8], hint: Option<&str>) -> Result<String> {
+    let (encoding, _) = detect_encoding(bytes, hint);
+    let (text, encoding, had_errors) = encoding.decode(bytes);
+
+    match had_errors {
+        true => Err(Error::Decode { encoding: encoding.name() }),
+        false => Ok(text.into_owned()),
+    }
+}
+
+// Looks for a <meta> declaring the encoding, skipping comments and the
+// attributes of other tags so a `charset=` inside them is not mistaken for
+// one.
+fn prescan(bytes: &[u8]) -> Option<&'static Encoding> {
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let rest = &bytes[pos..];
+
+        if rest.starts_with(b"<!--") {
+            pos += find(&rest[2..], b"-->").map_or(rest.len(), |end| end + 5);
+        } else if starts_with_ignore_case(rest, b"<meta") && rest.get(5).is_some_and(|b| b.is_ascii_whitespace() || *b == b'/') {
+            pos += 5;
+            if let Some(encoding) = meta_encoding(bytes, &mut pos) {
+                return Some(encoding);
+            }
+        } else if rest.len() > 1 && rest[0] == b'<' && (rest[1].is_ascii_alphabetic() || (rest[1] == b'/' && rest.get(2).is_some_and(u8::is_ascii_alphabetic))) {
+            pos += rest[1..].iter().position(|b| b.is_ascii_whitespace() || *b == b'>').map_or(rest.len(), |end| end + 1);
+            while attribute(bytes, &mut pos).is_some() {}
+        } else if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            pos += rest.iter().position(|b| *b == b'>').map_or(rest.len(), |end| end + 1);
+        } else {
+            pos += 1;
+        }
+    }
+
+    None
+}
+
+// Reads the attributes of a <meta>, returning the encoding it declares.
+fn meta_encoding(bytes: &[u8], pos: &mut usize) -> Option<&'static Encoding> {
+    let mut http_equiv_content_type = false;
+    let mut charset = None;
+    let mut content_charset = None;
+
+    while let Some((name, value)) = attribute(bytes, pos) {
+        match name.as_slice() {
+            b"http-equiv" => http_equiv_content_type = value.eq_ignore_ascii_case(b"content-type"),
+            b"content" if content_charset.is_none() => content_charset = charset_from_content(&value),
+            b"charset" if charset.is_none() => charset = Some(value),
+            _ => {}
+        }
+    }
+
+    let label = match (charset, content_charset) {
+        (Some(charset), _) => charset,
+        (None, Some(content_charset)) if http_equiv_content_type => content_charset,
+        _ => return None,
+    };
+
+    // A document that could be read far enough to find this is not UTF-16,
+    // whatever it claims.
+    Encoding::for_label(&label).map(|encoding| match encoding {
+        encoding if encoding == UTF_16BE || encoding == UTF_16LE => UTF_8,
+        encoding if encoding == X_USER_DEFINED => WINDOWS_1252,
+        encoding => encoding,
+    })
+}
+
+// Reads one attribute of a tag, lowercasing its name. Returns None at the
+// end of the tag.
+fn attribute(bytes: &[u8], pos: &mut usize) -> Option<(Vec<u8>, Vec<u8>)> {
+    while bytes.get(*pos).is_some_and(|b| b.is_ascii_whitespace() || *b == b'/') {
+        *pos += 1;
+    }
+    if bytes.get(*pos).is_none_or(|b| *b == b'>') {
+        *pos += 1;
+        return None;
+    }
+
+    let mut name = Vec::new();
+    while let Some(b) = bytes.get(*pos) {
+        if b.is_ascii_whitespace() || matches!(b, b'/' | b'>') || (*b == b'=' && !name.is_empty()) {
+            break;
+        }
+        name.push(b.to_ascii_lowercase());
+        *pos += 1;
+    }
+
+    while bytes.get(*pos).is_some_and(u8::is_ascii_whitespace) {
+        *pos += 1;
+    }
+    if bytes.get(*pos) != Some(&b'=') {
+        return Some((name, Vec::new()));
+    }
+    *pos += 1;
+    while bytes.get(*pos).is_some_and(u8::is_ascii_whitespace) {
+        *pos += 1;
+    }
+
+    let mut value = Vec::new();
+    match bytes.get(*pos) {
+        Some(quote) if *quote == b'"' || *quote == b'\'' => {
+            let quote = *quote;
+            *pos += 1;
+            while let Some(b) = bytes.get(*pos) {
+                *pos += 1;
+                if *b == quote {
+                    break;
+                }
+                value.push(b.to_ascii_lowercase());
+            }
+        },
+        _ => {
+            while let Some(b) = bytes.get(*pos) {
+                if b.is_ascii_whitespace() || *b == b'>' {
+                    break;
+                }
+                value.push(b.to_ascii_lowercase());
+                *pos += 1;
+            }
+        }
+    }
+
+    Some((name, value))
+}
+
+// Pulls the charset out of a Content-Type value like
+// `text/html; charset="shift_jis"`.
+fn charset_from_content(content: &[u8]) -> Option<Vec<u8>> {
+    let start = find(content, b"charset")? + 7;
+    let rest = content[start..].trim_ascii_start().strip_prefix(b"=")?.trim_ascii_start();
+
+    let value = match rest.first() {
+        Some(quote) if *quote == b'"' || *quote == b'\'' => {
+            let end = rest[1..].iter().position(|b| b == quote)?;
+            &rest[1..end + 1]
+        },
+        _ => {
+            let end = rest.iter().position(|b| b.is_ascii_whitespace() || *b == b';').unwrap_or(rest.len());
+            &rest[..end]
+        }
+    };
+
+    match value.is_empty() {
+        true => None,
+        false => Some(value.to_vec()),
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn starts_with_ignore_case(bytes: &[u8], prefix: &[u8]) -> bool {
+    bytes.len() >= prefix.len() && bytes[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+#[cfg(test)]
+mod test {
+    use encoding_rs::{SHIFT_JIS, UTF_16LE, UTF_8, WINDOWS_1252};
+
+    use crate::tuihtml::encoding::{decode_html, detect_encoding, EncodingSource};
+
+    #[test]
+    fn encoding_is_sniffed_in_order() {
+        let utf16: Vec<u8> = [0xFF, 0xFE].into_iter()
+            .chain("<p>h\u{e9}</p>".encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+        assert_eq!(detect_encoding(&utf16, Some("shift_jis")), (UTF_16LE, EncodingSource::Bom));
+        assert_eq!(decode_html(&utf16, None), "<p>h\u{e9}</p>");
+
+        let meta = b"<!-- <meta charset=utf-8> --><title x='charset=utf-8'>\xe9</title><meta http-equiv=Content-Type content=\"text/html; charset='Shift_JIS'\">";
+        assert_eq!(detect_encoding(meta, None), (SHIFT_JIS, EncodingSource::Meta));
+        assert_eq!(detect_encoding(meta, Some(" latin1 ")), (WINDOWS_1252, EncodingSource::Hint));
+        assert_eq!(detect_encoding(meta, Some("bogus")), (SHIFT_JIS, EncodingSource::Meta));
+
+        // Without http-equiv the content attribute says nothing.
+        assert_eq!(detect_encoding(b"<meta content='charset=shift_jis'>caf\xc3\xa9", None), (UTF_8, EncodingSource::Default));
+        assert_eq!(detect_encoding(b"caf\xe9", None), (WINDOWS_1252, EncodingSource::Default));
+    }
+
+    #[test]
+    fn documents_are_decoded_from_their_declared_encoding() {
+        let shift_jis = b"<meta charset=\"shift_jis\"><p>\x93\xfa\x96\x7b</p>";
+        assert_eq!(decode_html(shift_jis, None), "<meta charset=\"shift_jis\"><p>\u{65e5}\u{672c}</p>");
+
+        // A UTF-16 declaration in an ASCII-compatible document means UTF-8.
+        let utf8 = "<meta charset=utf-16><p>\u{65e5}</p>";
+        assert_eq!(decode_html(utf8.as_bytes(), None), utf8);
+    }
+}
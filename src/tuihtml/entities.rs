@@ -0,0 +1,163 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use entities::ENTITIES;
+
+// Longest name in the HTML5 table is "CounterClockwiseContourIntegral"
+const MAX_ENTITY_NAME_LEN: usize = 31;
+
+// Numeric references in the 0x80..=0x9F range are remapped the way browsers
+// do, since they almost always come from text authored in windows-1252.
+const WINDOWS_1252_REMAP: [(u32, char); 27] = [
+    (0x80, '\u{20AC}'), (0x82, '\u{201A}'), (0x83, '\u{0192}'), (0x84, '\u{201E}'),
+    (0x85, '\u{2026}'), (0x86, '\u{2020}'), (0x87, '\u{2021}'), (0x88, '\u{02C6}'),
+    (0x89, '\u{2030}'), (0x8A, '\u{0160}'), (0x8B, '\u{2039}'), (0x8C, '\u{0152}'),
+    (0x8E, '\u{017D}'), (0x91, '\u{2018}'), (0x92, '\u{2019}'), (0x93, '\u{201C}'),
+    (0x94, '\u{201D}'), (0x95, '\u{2022}'), (0x96, '\u{2013}'), (0x97, '\u{2014}'),
+    (0x98, '\u{02DC}'), (0x99, '\u{2122}'), (0x9A, '\u{0161}'), (0x9B, '\u{203A}'),
+    (0x9C, '\u{0153}'), (0x9E, '\u{017E}'), (0x9F, '\u{0178}'),
+];
+
+fn entity_table() -> &'static HashMap<&'static str, &'static str> {
+    static TABLE: OnceLock<HashMap<&'static str, &'static str>> = OnceLock::new();
+
+    // Keys are stored without the leading '&'. Legacy entities appear twice,
+    // once with and once without the trailing ';'.
+    TABLE.get_or_init(|| {
+        ENTITIES.iter()
+            .map(|entity| (&entity.entity[1..], entity.characters))
+            .collect()
+    })
+}
+
+/// Replaces named and numeric character references in `text`.
+///
+/// `in_attribute` enables the attribute-value rule where a legacy reference
+/// without a trailing ';' followed by '=' or an alphanumeric is left as is,
+/// so query strings like `?a=1&copy=2` survive.
+pub fn decode_entities(text: &str, in_attribute: bool) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp + 1..];
+
+        match decode_reference(rest, in_attribute) {
+            Some((value, consumed)) => {
+                decoded.push_str(value.as_str());
+                rest = &rest[consumed..];
+            },
+            None => decoded.push('&'),
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+// `input` starts just after the '&'. Returns the replacement text and the
+// number of bytes of `input` it replaces.
+fn decode_reference(input: &str, in_attribute: bool) -> Option<(String, usize)> {
+    match input.strip_prefix('#') {
+        Some(numeric) => decode_numeric(numeric).map(|(char, consumed)| (char.to_string(), consumed + 1)),
+        None => decode_named(input, in_attribute),
+    }
+}
+
+fn decode_numeric(input: &str) -> Option<(char, usize)> {
+    let (digits_start, radix) = match input.as_bytes().first() {
+        Some(b'x') | Some(b'X') => (1, 16),
+        _ => (0, 10),
+    };
+
+    let digits_len = input[digits_start..]
+        .bytes()
+        .take_while(|b| (*b as char).is_digit(radix))
+        .count();
+
+    if digits_len == 0 {
+        return None;
+    }
+
+    let digits = &input[digits_start..digits_start + digits_len];
+    let mut consumed = digits_start + digits_len;
+    if input[consumed..].starts_with(';') {
+        consumed += 1;
+    }
+
+    // Anything too large to parse is out of range anyway.
+    let code = u32::from_str_radix(digits, radix).unwrap_or(u32::MAX);
+    Some((numeric_to_char(code), consumed))
+}
+
+fn numeric_to_char(code: u32) -> char {
+    if code == 0 {
+        return char::REPLACEMENT_CHARACTER;
+    }
+
+    if let Some((_, char)) = WINDOWS_1252_REMAP.iter().find(|(c, _)| *c == code) {
+        return *char;
+    }
+
+    // Surrogates and values above U+10FFFF are rejected by from_u32.
+    char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+fn decode_named(input: &str, in_attribute: bool) -> Option<(String, usize)> {
+    let table = entity_table();
+    let name_len = input.bytes().take_while(|b| b.is_ascii_alphanumeric()).count();
+
+    if name_len == 0 {
+        return None;
+    }
+
+    if name_len <= MAX_ENTITY_NAME_LEN && input[name_len..].starts_with(';') {
+        if let Some(value) = table.get(&input[..=name_len]) {
+            return Some((value.to_string(), name_len + 1));
+        }
+    }
+
+    // Fall back to the longest legacy entity that prefixes the name,
+    // e.g. "&notit;" decodes as "¬it;".
+    for len in (1..=name_len.min(MAX_ENTITY_NAME_LEN)).rev() {
+        if let Some(value) = table.get(&input[..len]) {
+            let next = input[len..].chars().next();
+            if in_attribute && next.is_some_and(|c| c == '=' || c.is_ascii_alphanumeric()) {
+                return None;
+            }
+            return Some((value.to_string(), len));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tuihtml::entities::decode_entities;
+
+    #[test]
+    fn named_references_are_decoded() {
+        assert_eq!(decode_entities("A &mdash; B &apos;C&apos; &bull; &copy; 2026", false), "A \u{2014} B 'C' \u{2022} \u{00A9} 2026");
+        assert_eq!(decode_entities("&acE;", false), "\u{223E}\u{0333}");
+        assert_eq!(decode_entities("AT&T &unknown; &", false), "AT&T &unknown; &");
+    }
+
+    #[test]
+    fn numeric_references_are_decoded() {
+        assert_eq!(decode_entities("it&#8217;s &#x2014;&#X41;", false), "it\u{2019}s \u{2014}A");
+        assert_eq!(decode_entities("&#150; &#0; &#xD800; &#1114112; &#99999999999;", false), "\u{2013} \u{FFFD} \u{FFFD} \u{FFFD} \u{FFFD}");
+        assert_eq!(decode_entities("&#; &#x;", false), "&#; &#x;");
+    }
+
+    #[test]
+    fn legacy_references_without_semicolon() {
+        assert_eq!(decode_entities("&copy 2026 &ampx &notit;", false), "\u{00A9} 2026 &x \u{00AC}it;");
+        assert_eq!(decode_entities("/feed?a=1&copy=2&amp;b=3", true), "/feed?a=1&copy=2&b=3");
+        assert_eq!(decode_entities("&copy 2026", true), "\u{00A9} 2026");
+    }
+}
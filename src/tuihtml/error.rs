@@ -0,0 +1,56 @@
+use std::{fmt, io};
+
+use crate::tuihtml::source::SourceSpan;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    Io(io::Error),
+    // The bytes were not valid in the encoding they were decoded as.
+    Decode { encoding: &'static str },
+    // The input is longer than ParseOptions::max_document_size.
+    DocumentTooLarge { size: usize, limit: usize },
+    // An element would have been nested deeper than ParseOptions::max_depth.
+    NestingTooDeep { limit: usize, span: SourceSpan },
+    // The input is more than ParseOptions::max_tokens tokens.
+    TooManyTokens { limit: usize },
+    // An element has more than ParseOptions::max_attributes attributes.
+    TooManyAttributes { limit: usize },
+    // The document lays out to more than ParseOptions::max_lines lines.
+    TooManyLines { limit: usize },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "failed to read HTML: {}", error),
+            Error::Decode { encoding } => write!(f, "HTML is not valid {}", encoding),
+            Error::DocumentTooLarge { size, limit } => {
+                write!(f, "document is {} bytes, more than the limit of {}", size, limit)
+            },
+            Error::NestingTooDeep { limit, span } => {
+                write!(f, "{}:{}: elements nested more than {} deep", span.start.line, span.start.column, limit)
+            },
+            Error::TooManyTokens { limit } => write!(f, "document has more than {} tokens", limit),
+            Error::TooManyAttributes { limit } => write!(f, "element has more than {} attributes", limit),
+            Error::TooManyLines { limit } => write!(f, "document is more than {} lines long", limit),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
@@ -1,208 +1,464 @@
-use std::collections::HashMap;
-
-use ratatui::{style::Modifier};
-
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub enum HtmlTag {
-    HTML,
-    HEAD,
-    BODY,
-    TITLE,
-    META,
-    LINK,
-    STYLE,
-    DIV,
-    SPAN,
-    H1,
-    H2,
-    H3,
-    H4,
-    H5,
-    H6,
-    P,
-    A,
-    BOLD,
-    U,
-    S,
-    DEL,
-    BR,
-    HR,
-    IMG,
-    LABEL,
-    TABLE,
-    TR,
-    TH,
-    TD,
-    UL,
-    OL,
-    LI,
-    TEXTAREA,
-    IFRAME,
-    SOURCE,
-    NAV,
-    HEADER,
-    FOOTER,
-    SECTION,
-    ARTICLE,
-    ASIDE,
-    MAIN,
-    FIGURE,
-    FIGCAPTION,
-    STRONG,
-    EM,
-    I,
-    CODE,
-    PRE,
-    BLOCKQUOTE,
-    CITE,
-    ABBR,
-    TIME,
-    DATA,
-    PROGRESS,
-    METER,
-    DETAILS,
-    SUMMARY,
-    DIALOG,
-    CANVAS,
-    SVG,
-    MATH,
-    TEMPLATE,
-    SLOT,
-    OUTPUT,
-    FIELDSET,
-    LEGEND,
-    DATALIST,
-    OPTGROUP,
-    SMALL,
-    AREA,
-    COL,
-    COLGROUP,
-    CAPTION,
-    THEAD,
-    TBODY,
-    TFOOT,
-    UNKNOWN,
+// Every tag the parser knows, with its name in markup. The enum,
+// `from_string` and `name` are all generated from this one list.
+macro_rules! html_tags {
+    ($($tag:ident => $name:literal,)*) => {
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        pub enum HtmlTag {
+            $($tag,)*
+            // Any other tag, by its lowercased name.
+            UNKNOWN(String),
+        }
+
+        impl HtmlTag {
+            pub fn from_string(string: &str) -> HtmlTag {
+                let name = string.to_lowercase();
+                match name.as_str() {
+                    $($name => HtmlTag::$tag,)*
+                    _ => HtmlTag::UNKNOWN(name)
+                }
+            }
+
+            pub fn name(&self) -> &str {
+                match self {
+                    $(HtmlTag::$tag => $name,)*
+                    HtmlTag::UNKNOWN(name) => name,
+                }
+            }
+        }
+    };
+}
+
+html_tags! {
+    HTML => "html",
+    HEAD => "head",
+    BODY => "body",
+    TITLE => "title",
+    META => "meta",
+    LINK => "link",
+    STYLE => "style",
+    SCRIPT => "script",
+    DIV => "div",
+    SPAN => "span",
+    H1 => "h1",
+    H2 => "h2",
+    H3 => "h3",
+    H4 => "h4",
+    H5 => "h5",
+    H6 => "h6",
+    P => "p",
+    A => "a",
+    BOLD => "b",
+    U => "u",
+    S => "s",
+    DEL => "del",
+    BR => "br",
+    HR => "hr",
+    IMG => "img",
+    LABEL => "label",
+    TABLE => "table",
+    TR => "tr",
+    TH => "th",
+    TD => "td",
+    UL => "ul",
+    OL => "ol",
+    LI => "li",
+    TEXTAREA => "textarea",
+    INPUT => "input",
+    IFRAME => "iframe",
+    SOURCE => "source",
+    NAV => "nav",
+    HEADER => "header",
+    FOOTER => "footer",
+    SECTION => "section",
+    ARTICLE => "article",
+    ASIDE => "aside",
+    MAIN => "main",
+    FIGURE => "figure",
+    FIGCAPTION => "figcaption",
+    STRONG => "strong",
+    EM => "em",
+    FONT => "font",
+    I => "i",
+    CODE => "code",
+    PRE => "pre",
+    BLOCKQUOTE => "blockquote",
+    CITE => "cite",
+    ABBR => "abbr",
+    TIME => "time",
+    DATA => "data",
+    PROGRESS => "progress",
+    METER => "meter",
+    DETAILS => "details",
+    SUMMARY => "summary",
+    DIALOG => "dialog",
+    CANVAS => "canvas",
+    SVG => "svg",
+    MATH => "math",
+    TEMPLATE => "template",
+    SLOT => "slot",
+    OUTPUT => "output",
+    FIELDSET => "fieldset",
+    LEGEND => "legend",
+    DATALIST => "datalist",
+    OPTGROUP => "optgroup",
+    SMALL => "small",
+    AREA => "area",
+    WBR => "wbr",
+    EMBED => "embed",
+    TRACK => "track",
+    COL => "col",
+    COLGROUP => "colgroup",
+    CAPTION => "caption",
+    THEAD => "thead",
+    TBODY => "tbody",
+    TFOOT => "tfoot",
 }
 
 impl HtmlTag {
-    pub fn from_string(string: &str) -> HtmlTag {
-        match string.to_lowercase().as_str() {
-            "html" => HtmlTag::HTML,
-            "head" => HtmlTag::HEAD,
-            "body" => HtmlTag::BODY,
-            "title" => HtmlTag::TITLE,
-            "meta" => HtmlTag::META,
-            "link" => HtmlTag::LINK,
-            "style" => HtmlTag::STYLE,
-            "div" => HtmlTag::DIV,
-            "span" => HtmlTag::SPAN,
-            "h1" => HtmlTag::H1,
-            "h2" => HtmlTag::H2,
-            "h3" => HtmlTag::H3,
-            "h4" => HtmlTag::H4,
-            "h5" => HtmlTag::H5,
-            "h6" => HtmlTag::H6,
-            "p" => HtmlTag::P,
-            "a" => HtmlTag::A,
-            "b" => HtmlTag::BOLD,
-            "u" => HtmlTag::U,
-            "s" => HtmlTag::S,
-            "del" => HtmlTag::DEL,
-            "br" => HtmlTag::BR,
-            "hr" => HtmlTag::HR,
-            "img" => HtmlTag::IMG,
-            "label" => HtmlTag::LABEL,
-            "table" => HtmlTag::TABLE,
-            "tr" => HtmlTag::TR,
-            "th" => HtmlTag::TH,
-            "td" => HtmlTag::TD,
-            "ul" => HtmlTag::UL,
-            "ol" => HtmlTag::OL,
-            "li" => HtmlTag::LI,
-            "textarea" => HtmlTag::TEXTAREA,
-            "iframe" => HtmlTag::IFRAME,
-            "source" => HtmlTag::SOURCE,
-            "nav" => HtmlTag::NAV,
-            "header" => HtmlTag::HEADER,
-            "footer" => HtmlTag::FOOTER,
-            "section" => HtmlTag::SECTION,
-            "article" => HtmlTag::ARTICLE,
-            "aside" => HtmlTag::ASIDE,
-            "main" => HtmlTag::MAIN,
-            "figure" => HtmlTag::FIGURE,
-            "figcaption" => HtmlTag::FIGCAPTION,
-            "strong" => HtmlTag::STRONG,
-            "em" => HtmlTag::EM,
-            "i" => HtmlTag::I,
-            "code" => HtmlTag::CODE,
-            "pre" => HtmlTag::PRE,
-            "blockquote" => HtmlTag::BLOCKQUOTE,
-            "cite" => HtmlTag::CITE,
-            "abbr" => HtmlTag::ABBR,
-            "time" => HtmlTag::TIME,
-            "data" => HtmlTag::DATA,
-            "progress" => HtmlTag::PROGRESS,
-            "meter" => HtmlTag::METER,
-            "details" => HtmlTag::DETAILS,
-            "summary" => HtmlTag::SUMMARY,
-            "dialog" => HtmlTag::DIALOG,
-            "canvas" => HtmlTag::CANVAS,
-            "svg" => HtmlTag::SVG,
-            "math" => HtmlTag::MATH,
-            "template" => HtmlTag::TEMPLATE,
-            "slot" => HtmlTag::SLOT,
-            "output" => HtmlTag::OUTPUT,
-            "fieldset" => HtmlTag::FIELDSET,
-            "legend" => HtmlTag::LEGEND,
-            "datalist" => HtmlTag::DATALIST,
-            "optgroup" => HtmlTag::OPTGROUP,
-            "small" => HtmlTag::SMALL,
-            "area" => HtmlTag::AREA,
-            "col" => HtmlTag::COL,
-            "colgroup" => HtmlTag::COLGROUP,
-            "caption" => HtmlTag::CAPTION,
-            "thead" => HtmlTag::THEAD,
-            "tbody" => HtmlTag::TBODY,
-            "tfoot" => HtmlTag::TFOOT,
-            _ => HtmlTag::UNKNOWN
-        }
+    // Void elements never have content or an end tag, whether or not the
+    // source wrote them as `<br>` or `<br/>`.
+    pub fn is_void(&self) -> bool {
+        matches!(self,
+            HtmlTag::BR |
+            HtmlTag::HR |
+            HtmlTag::IMG |
+            HtmlTag::META |
+            HtmlTag::LINK |
+            HtmlTag::INPUT |
+            HtmlTag::SOURCE |
+            HtmlTag::COL |
+            HtmlTag::AREA |
+            HtmlTag::WBR |
+            HtmlTag::EMBED |
+            HtmlTag::TRACK
+        )
     }
 
-    pub fn to_modifiers(&self) -> Vec<Modifier> {
-        match self {
+    // Elements tracked in the list of active formatting elements, which
+    // lets misnested markup like `<b><i>x</b>y</i>` be repaired.
+    pub fn is_formatting(&self) -> bool {
+        matches!(self,
+            HtmlTag::A |
             HtmlTag::BOLD |
+            HtmlTag::CODE |
+            HtmlTag::EM |
+            HtmlTag::FONT |
+            HtmlTag::I |
+            HtmlTag::S |
+            HtmlTag::SMALL |
             HtmlTag::STRONG |
+            HtmlTag::U
+        )
+    }
+
+    // The HTML5 "special" category: elements that stray end tags and the
+    // adoption agency algorithm will never reach past.
+    pub fn is_special(&self) -> bool {
+        matches!(self,
+            HtmlTag::AREA |
+            HtmlTag::ARTICLE |
+            HtmlTag::ASIDE |
+            HtmlTag::BLOCKQUOTE |
+            HtmlTag::BODY |
+            HtmlTag::BR |
+            HtmlTag::CAPTION |
+            HtmlTag::COL |
+            HtmlTag::COLGROUP |
+            HtmlTag::DETAILS |
+            HtmlTag::DIV |
+            HtmlTag::EMBED |
+            HtmlTag::FIELDSET |
+            HtmlTag::FIGCAPTION |
+            HtmlTag::FIGURE |
+            HtmlTag::FOOTER |
             HtmlTag::H1 |
             HtmlTag::H2 |
             HtmlTag::H3 |
             HtmlTag::H4 |
             HtmlTag::H5 |
-            HtmlTag::H6 => vec![Modifier::BOLD],
+            HtmlTag::H6 |
+            HtmlTag::HEAD |
+            HtmlTag::HEADER |
+            HtmlTag::HR |
+            HtmlTag::HTML |
+            HtmlTag::IFRAME |
+            HtmlTag::IMG |
+            HtmlTag::INPUT |
+            HtmlTag::LI |
+            HtmlTag::LINK |
+            HtmlTag::MAIN |
+            HtmlTag::META |
+            HtmlTag::NAV |
+            HtmlTag::OL |
+            HtmlTag::P |
+            HtmlTag::PRE |
+            HtmlTag::SCRIPT |
+            HtmlTag::SECTION |
+            HtmlTag::SOURCE |
+            HtmlTag::STYLE |
+            HtmlTag::SUMMARY |
+            HtmlTag::TABLE |
+            HtmlTag::TBODY |
+            HtmlTag::TD |
+            HtmlTag::TEMPLATE |
+            HtmlTag::TEXTAREA |
+            HtmlTag::TFOOT |
+            HtmlTag::TH |
+            HtmlTag::THEAD |
+            HtmlTag::TITLE |
+            HtmlTag::TR |
+            HtmlTag::TRACK |
+            HtmlTag::UL |
+            HtmlTag::WBR
+        )
+    }
+
+    // Content of these is never shown as page text.
+    pub fn is_hidden(&self) -> bool {
+        // The elements the tokenizer reads as raw text, but for TEXTAREA.
+        matches!(self, HtmlTag::SCRIPT | HtmlTag::STYLE | HtmlTag::TITLE | HtmlTag::IFRAME)
+    }
+
+    pub fn is_heading(&self) -> bool {
+        matches!(self, HtmlTag::H1 | HtmlTag::H2 | HtmlTag::H3 | HtmlTag::H4 | HtmlTag::H5 | HtmlTag::H6)
+    }
+}
+
+// Attributes in the order they appear in the source. Elements rarely carry
+// more than a handful, so a linear scan beats hashing and keeps output
+// deterministic.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Attributes(Vec<(String, String)>);
+
+impl Attributes {
+    pub fn new() -> Self {
+        Attributes(Vec::new())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.iter().any(|(key, _)| key == name)
+    }
 
-            HtmlTag::EM | HtmlTag::I => vec![Modifier::ITALIC],
+    // Keeps the first value for a name, like browsers do for duplicated
+    // attributes. Returns false if the attribute was already present.
+    pub fn insert(&mut self, name: String, value: String) -> bool {
+        if self.contains(&name) {
+            return false;
+        }
+        self.0.push((name, value));
+        true
+    }
 
-            HtmlTag::U | HtmlTag::A => vec![Modifier::UNDERLINED],
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
 
-            HtmlTag::S | HtmlTag::DEL => vec![Modifier::CROSSED_OUT],
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
 
-            _ => vec![],
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+impl<const N: usize> From<[(String, String); N]> for Attributes {
+    fn from(attributes: [(String, String); N]) -> Self {
+        attributes.into_iter().collect()
+    }
+}
+
+impl FromIterator<(String, String)> for Attributes {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> Self {
+        let mut attributes = Attributes::new();
+        for (name, value) in iter {
+            attributes.insert(name, value);
         }
+        attributes
+    }
+}
+
+// The HTML "rules for parsing integers": leading whitespace and an optional
+// sign, then as many digits as there are, ignoring anything after them.
+// Numbers too large for an i64 saturate.
+fn parse_integer(value: &str) -> Option<i64> {
+    let value = value.trim_start();
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+
+    let digits_len = digits.bytes().take_while(|b| b.is_ascii_digit()).count();
+    if digits_len == 0 {
+        return None;
+    }
+    // Parsed with the sign, since i64::MIN can't be negated from a positive.
+    let sign = if negative { "-" } else { "" };
+    match format!("{}{}", sign, &digits[..digits_len]).parse() {
+        Ok(number) => Some(number),
+        Err(_) => Some(if negative { i64::MIN } else { i64::MAX }),
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HtmlElement {
     pub tag: HtmlTag,
-    pub attributes: HashMap<String, String>,
-    pub closing: bool
+    pub attributes: Attributes,
+    pub closing: bool,
+    pub self_closing: bool,
 }
 
 impl HtmlElement {
-    pub fn new(tag: HtmlTag, attributes: HashMap<String, String>, closing: bool) -> Self {
+    pub fn new(tag: HtmlTag, attributes: Attributes, closing: bool, self_closing: bool) -> Self {
         HtmlElement {
             tag,
             attributes,
-            closing
+            closing,
+            self_closing
         }
     }
+
+    // The trailing '/' only means something for void elements (where it is
+    // redundant) and for foreign content like `<svg/>` or `<path/>`. On other
+    // HTML elements browsers ignore it, so `<div/>` still opens a div.
+    pub fn is_empty(&self) -> bool {
+        self.tag.is_void() ||
+            (self.self_closing && matches!(self.tag, HtmlTag::SVG | HtmlTag::MATH | HtmlTag::UNKNOWN(_)))
+    }
+
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name)
+    }
+
+    pub fn has_attr(&self, name: &str) -> bool {
+        self.attributes.contains(name)
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.attr("id").filter(|id| !id.is_empty())
+    }
+
+    pub fn classes(&self) -> impl Iterator<Item = &str> {
+        self.attr("class").unwrap_or_default().split_ascii_whitespace()
+    }
+
+    pub fn has_class(&self, class: &str) -> bool {
+        self.classes().any(|c| c == class)
+    }
+
+    pub fn href(&self) -> Option<&str> {
+        self.attr("href")
+    }
+
+    pub fn src(&self) -> Option<&str> {
+        self.attr("src")
+    }
+
+    pub fn alt(&self) -> Option<&str> {
+        self.attr("alt")
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.attr("title")
+    }
+
+    pub fn value(&self) -> Option<&str> {
+        self.attr("value")
+    }
+
+    // Number of columns a table cell spans. Missing, zero or garbage values
+    // mean 1 and, like browsers, anything above 1000 is clamped.
+    pub fn colspan(&self) -> usize {
+        self.attr("colspan")
+            .and_then(parse_integer)
+            .filter(|span| *span > 0)
+            .map_or(1, |span| span.min(1000) as usize)
+    }
+
+    // Number of rows a table cell spans. Like colspan, except that 0, which
+    // means to the end of the row group, is taken as 1.
+    pub fn rowspan(&self) -> usize {
+        self.attr("rowspan")
+            .and_then(parse_integer)
+            .filter(|span| *span > 0)
+            .map_or(1, |span| span.min(65534) as usize)
+    }
+
+    // First number of an ordered list.
+    pub fn start(&self) -> Option<i64> {
+        self.attr("start").and_then(parse_integer)
+    }
+
+    // Ordinal value of a list item, from `<li value="...">`.
+    pub fn ordinal(&self) -> Option<i64> {
+        self.value().and_then(parse_integer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tuihtml::html::{Attributes, HtmlElement, HtmlTag};
+
+    fn element(attributes: &[(&str, &str)]) -> HtmlElement {
+        let attributes = attributes.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        HtmlElement::new(HtmlTag::TD, attributes, false, false)
+    }
+
+    #[test]
+    fn attributes_keep_source_order_and_first_value() {
+        let mut attributes = Attributes::from([("src".into(), "a.png".into()), ("alt".into(), "A".into())]);
+
+        assert!(!attributes.insert("src".into(), "b.png".into()));
+        assert!(attributes.insert("id".into(), "logo".into()));
+
+        assert_eq!(attributes.iter().collect::<Vec<_>>(), vec![("src", "a.png"), ("alt", "A"), ("id", "logo")]);
+        assert_eq!(attributes.get("src"), Some("a.png"));
+        assert!(!attributes.contains("title"));
+    }
+
+    #[test]
+    fn typed_accessors() {
+        let cell = element(&[("id", "total"), ("class", " num  wide "), ("colspan", " 3px"), ("title", "Sum")]);
+
+        assert_eq!(cell.id(), Some("total"));
+        assert_eq!(cell.classes().collect::<Vec<_>>(), vec!["num", "wide"]);
+        assert!(cell.has_class("wide"));
+        assert_eq!(cell.colspan(), 3);
+        assert_eq!(cell.title(), Some("Sum"));
+        assert!(cell.has_attr("colspan"));
+        assert_eq!(cell.href(), None);
+
+        assert_eq!(element(&[("colspan", "0")]).colspan(), 1);
+        assert_eq!(element(&[("colspan", "5000")]).colspan(), 1000);
+        assert_eq!(element(&[("start", "-2")]).start(), Some(-2));
+        assert_eq!(element(&[("value", "x")]).ordinal(), None);
+        assert_eq!(element(&[("id", "")]).id(), None);
+
+        assert_eq!(element(&[]).rowspan(), 1);
+        assert_eq!(element(&[("rowspan", "4")]).rowspan(), 4);
+        assert_eq!(element(&[("rowspan", "0")]).rowspan(), 1);
+        assert_eq!(element(&[("rowspan", "-3")]).rowspan(), 1);
+        assert_eq!(element(&[("rowspan", "two")]).rowspan(), 1);
+        assert_eq!(element(&[("rowspan", "65535")]).rowspan(), 65534);
+        assert_eq!(element(&[("rowspan", "99999999999999999999")]).rowspan(), 65534);
+        assert_eq!(element(&[("colspan", "99999999999999999999")]).colspan(), 1000);
+        assert_eq!(element(&[("start", "-99999999999999999999")]).start(), Some(i64::MIN));
+
+        let image = element(&[("src", "/logo.png"), ("alt", "")]);
+        assert_eq!(image.src(), Some("/logo.png"));
+        assert_eq!(image.alt(), Some(""));
+        assert_eq!(image.value(), None);
+        assert_eq!(element(&[("value", "3")]).value(), Some("3"));
+        assert_eq!(element(&[("value", "3")]).ordinal(), Some(3));
+    }
 }
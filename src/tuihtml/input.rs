@@ -0,0 +1,169 @@
+use std::{collections::VecDeque, fmt, io::{self, BufRead, BufReader, Read}, str::Chars};
+
+enum Source<'a> {
+    Chars(Chars<'a>),
+    Reader(Box<dyn BufRead + 'a>),
+}
+
+// The characters the tokenizer reads, either borrowed from a string or
+// decoded as UTF-8 from a reader a buffer at a time. Characters it peeks at
+// are held in `lookahead` so a reader never has to be rewound.
+pub struct HtmlInput<'a> {
+    source: Source<'a>,
+    lookahead: VecDeque<char>,
+    // Bytes at the end of the last read that stop partway through a
+    // multi-byte character.
+    partial: Vec<u8>,
+    error: Option<io::Error>,
+    // Set once reading or peeking has run into the end of the input.
+    exhausted: bool,
+}
+
+impl fmt::Debug for HtmlInput<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HtmlInput")
+            .field("lookahead", &self.lookahead)
+            .field("error", &self.error)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> HtmlInput<'a> {
+    pub fn from_chars(chars: Chars<'a>) -> Self {
+        HtmlInput::new(Source::Chars(chars))
+    }
+
+    pub fn from_reader(reader: impl Read + 'a) -> Self {
+        HtmlInput::new(Source::Reader(Box::new(BufReader::new(reader))))
+    }
+
+    fn new(source: Source<'a>) -> Self {
+        HtmlInput {
+            source,
+            lookahead: VecDeque::new(),
+            partial: Vec::new(),
+            error: None,
+            exhausted: false,
+        }
+    }
+
+    // The character `n` places ahead of the one next() would return.
+    pub fn peek(&mut self, n: usize) -> Option<char> {
+        while self.lookahead.len() <= n {
+            if !self.fill() {
+                return None;
+            }
+        }
+        self.lookahead.get(n).copied()
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted
+    }
+
+    // The first error the reader returned. Input stops there, as if the
+    // document had ended.
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    // Moves at least one more character into `lookahead`, returning false
+    // once the input is exhausted.
+    fn fill(&mut self) -> bool {
+        let filled = self.read_more();
+        if !filled {
+            self.exhausted = true;
+        }
+        filled
+    }
+
+    fn read_more(&mut self) -> bool {
+        let reader = match &mut self.source {
+            Source::Chars(chars) => return match chars.next() {
+                Some(char) => {
+                    self.lookahead.push_back(char);
+                    true
+                },
+                None => false,
+            },
+            Source::Reader(reader) => reader,
+        };
+
+        let before = self.lookahead.len();
+
+        while self.lookahead.len() == before {
+            if self.error.is_some() {
+                return false;
+            }
+
+            let buffer = match reader.fill_buf() {
+                Ok(buffer) => buffer,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => {
+                    self.error = Some(error);
+                    return false;
+                },
+            };
+
+            if buffer.is_empty() {
+                // A character cut off by the end of the input is invalid.
+                if self.partial.is_empty() {
+                    return false;
+                }
+                self.partial.clear();
+                self.lookahead.push_back(char::REPLACEMENT_CHARACTER);
+                break;
+            }
+
+            self.partial.extend_from_slice(buffer);
+            let consumed = buffer.len();
+            reader.consume(consumed);
+
+            let decoded = decode_utf8(&self.partial, &mut self.lookahead);
+            self.partial.drain(..decoded);
+        }
+
+        true
+    }
+}
+
+impl Iterator for HtmlInput<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        if self.lookahead.is_empty() {
+            self.fill();
+        }
+        self.lookahead.pop_front()
+    }
+}
+
+// Decodes as much of `bytes` as forms complete characters, replacing invalid
+// sequences with U+FFFD, and returns how many bytes were used.
+fn decode_utf8(bytes: &[u8], out: &mut VecDeque<char>) -> usize {
+    let mut used = 0;
+
+    loop {
+        match std::str::from_utf8(&bytes[used..]) {
+            Ok(text) => {
+                out.extend(text.chars());
+                return bytes.len();
+            },
+            Err(error) => {
+                let valid = &bytes[used..used + error.valid_up_to()];
+                // Safe to unwrap, from_utf8 just checked these bytes.
+                out.extend(std::str::from_utf8(valid).unwrap().chars());
+                used += error.valid_up_to();
+
+                match error.error_len() {
+                    Some(len) => {
+                        out.push_back(char::REPLACEMENT_CHARACTER);
+                        used += len;
+                    },
+                    // An incomplete character, which the next read may finish.
+                    None => return used,
+                }
+            },
+        }
+    }
+}
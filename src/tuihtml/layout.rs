@@ -0,0 +1,528 @@
+use ratatui::{layout::Alignment, style::Style, text::{Line, Span}};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::tuihtml::{source::SourceSpan, table::TableLines, theme::{BlockStyle, RuleLine}};
+
+// How a line sits in the blocks around it, kept so that it can be wrapped
+// to the width it is rendered at.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct LineLayout {
+    // Columns at the start of the line taken by the blocks it is in, their
+    // indents and list markers, rather than by its text.
+    pub(crate) indent: usize,
+    // What goes in those columns on the lines the text wraps onto: the same
+    // indents and gutters, with blanks under the list markers.
+    pub(crate) hanging: Vec<Span<'static>>,
+}
+
+// The lines a walk has laid out so far.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct LaidOut<'a> {
+    pub(crate) lines: Vec<Line<'a>>,
+    // Source span of every line.
+    pub(crate) sources: Vec<Option<SourceSpan>>,
+    pub(crate) layouts: Vec<LineLayout>,
+    // The rules and tables among the lines, to be fitted to the area at
+    // render time.
+    pub(crate) rules: Vec<RuleLine>,
+    pub(crate) tables: Vec<TableLines<'a>>,
+}
+
+impl<'a> LaidOut<'a> {
+    pub(crate) fn truncate(&mut self, lines: usize) {
+        self.lines.truncate(lines);
+        self.sources.truncate(lines);
+        self.layouts.truncate(lines);
+        self.rules.retain(|rule| rule.line < lines);
+        self.tables.retain(|table| table.line + table.lines <= lines);
+    }
+
+    pub(crate) fn fit(&self, width: usize) -> Vec<Line<'a>> {
+        fit_lines(&self.lines, &self.layouts, &self.rules, &self.tables, width)
+    }
+}
+
+// A block element the walk is inside of.
+#[derive(Debug, Clone)]
+struct BlockBox {
+    style: BlockStyle,
+    // A list marker to start the block's first line with.
+    marker: Option<Span<'static>>,
+    // Whether any of the block's lines have been laid out, after which its
+    // marker is only blanks.
+    started: bool,
+    // Whether there are blocks inside this one.
+    has_blocks: bool,
+}
+
+// The block boxes the walk is in. Every line goes through here to be
+// indented for the blocks around it, and the margins between blocks are
+// collapsed: of two margins next to each other, only the larger is drawn.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BlockFlow {
+    blocks: Vec<BlockBox>,
+    // The margin still to be drawn, held back until whatever comes next so
+    // that margins next to it can collapse into it.
+    margin: usize,
+    // How many of the blocks the margin is inside of.
+    margin_depth: usize,
+    margin_source: Option<SourceSpan>,
+}
+
+impl BlockFlow {
+    // Starts a block inside the current one. The line style of `style` is
+    // expected to be fitted to the terminal already.
+    pub(crate) fn open(&mut self, out: &mut LaidOut, style: BlockStyle, source: SourceSpan) {
+        if let Some(parent) = self.blocks.last_mut() {
+            parent.has_blocks = true;
+        }
+        self.add_margin(style.margin_top, source);
+
+        let padding = style.padding_top;
+        self.blocks.push(BlockBox { style, marker: None, started: false, has_blocks: false });
+        self.pad(out, padding, source);
+    }
+
+    // Starts the current block's first line with `marker` and indents the
+    // lines after it past the marker.
+    pub(crate) fn set_marker(&mut self, marker: Span<'static>) {
+        if let Some(block) = self.blocks.last_mut() {
+            block.marker = Some(marker);
+        }
+    }
+
+    // Whether the current block has blocks inside it, which makes the text
+    // around them lines of their own.
+    pub(crate) fn has_blocks(&self) -> bool {
+        self.blocks.last().is_some_and(|block| block.has_blocks)
+    }
+
+    pub(crate) fn close(&mut self, out: &mut LaidOut, source: SourceSpan) {
+        let Some(block) = self.blocks.last() else {
+            return;
+        };
+        let (unmarked, padding) = (block.marker.is_some() && !block.started, block.style.padding_bottom);
+
+        // An empty list item still shows its marker.
+        if unmarked {
+            self.push(out, vec![Line::default()], Vec::new(), Some(source));
+        }
+
+        self.pad(out, padding, source);
+        if let Some(block) = self.blocks.pop() {
+            self.add_margin(block.style.margin_bottom, source);
+        }
+    }
+
+    // Lays out lines of the current block, or of the text between blocks,
+    // with `rules` counted from the first of them.
+    pub(crate) fn push<'a>(&mut self, out: &mut LaidOut<'a>, lines: Vec<Line<'a>>, rules: Vec<RuleLine>, source: Option<SourceSpan>) {
+        if lines.is_empty() {
+            return;
+        }
+        self.draw_margin(out);
+
+        let first = out.lines.len();
+        out.rules.extend(rules.into_iter().map(|rule| RuleLine { line: first + rule.line, ..rule }));
+
+        for mut line in lines {
+            if let Some(block) = self.blocks.last() {
+                line.style = block.style.line_style.patch(line.style);
+                if line.alignment.is_none() {
+                    line.alignment = block.style.alignment;
+                }
+            }
+
+            let (prefix, indent) = self.prefix(self.blocks.len(), true);
+            let (hanging, _) = self.prefix(self.blocks.len(), false);
+            add_line(out, line, prefix, hanging, indent, source);
+            for block in &mut self.blocks {
+                block.started = true;
+            }
+        }
+    }
+
+    // Makes a line of the text before a block, unless it is only the
+    // whitespace between blocks.
+    pub(crate) fn push_text<'a>(&mut self, out: &mut LaidOut<'a>, spans: Vec<Span<'a>>, source: Option<SourceSpan>) {
+        if spans.iter().any(|span| !span.content.trim().is_empty()) {
+            self.push(out, vec![Line::from(spans)], Vec::new(), source);
+        }
+    }
+
+    // Draws the margin left after the last block.
+    pub(crate) fn finish(&mut self, out: &mut LaidOut) {
+        self.draw_margin(out);
+    }
+
+    fn add_margin(&mut self, margin: usize, source: SourceSpan) {
+        if margin == 0 {
+            return;
+        }
+
+        self.margin_depth = match self.margin {
+            0 => self.blocks.len(),
+            _ => self.margin_depth.min(self.blocks.len()),
+        };
+        self.margin = self.margin.max(margin);
+        self.margin_source = Some(source);
+    }
+
+    fn draw_margin(&mut self, out: &mut LaidOut) {
+        // Nothing to keep apart from at the very start, so the margin
+        // collapses into the top edge.
+        if out.lines.is_empty() {
+            self.margin = 0;
+            return;
+        }
+
+        let (prefix, indent) = self.prefix(self.margin_depth.min(self.blocks.len()), false);
+
+        for _ in 0..std::mem::take(&mut self.margin) {
+            add_line(out, Line::raw("\u{00A0}"), prefix.clone(), prefix.clone(), indent, self.margin_source);
+        }
+    }
+
+    // Blank lines inside the current block.
+    fn pad(&mut self, out: &mut LaidOut, lines: usize, source: SourceSpan) {
+        if lines == 0 {
+            return;
+        }
+        self.draw_margin(out);
+
+        let (prefix, indent) = self.prefix(self.blocks.len(), false);
+        let style = self.blocks.last().map_or(Style::default(), |block| block.style.line_style);
+        for _ in 0..lines {
+            add_line(out, Line::raw("\u{00A0}").style(style), prefix.clone(), prefix.clone(), indent, Some(source));
+        }
+    }
+
+    // What goes before a line inside the first `depth` blocks, and how wide
+    // it is. List markers are only drawn on the first line of text, for
+    // any other they are blanks. Gutters are drawn on every line.
+    fn prefix(&self, depth: usize, text: bool) -> (Vec<Span<'static>>, usize) {
+        let mut spans = Vec::new();
+        let mut width = 0;
+        let mut blanks = 0;
+
+        for block in &self.blocks[..depth] {
+            match &block.style.gutter {
+                Some(gutter) => {
+                    spans.extend(blank(blanks));
+                    spans.push(gutter.clone());
+                    width += blanks + gutter.width();
+                    blanks = block.style.indent.saturating_sub(gutter.width());
+                },
+                None => blanks += block.style.indent,
+            }
+
+            if let Some(marker) = &block.marker {
+                match text && !block.started {
+                    true => {
+                        spans.extend(blank(blanks));
+                        spans.push(marker.clone());
+                        width += blanks + marker.width();
+                        blanks = 0;
+                    },
+                    false => blanks += marker.width(),
+                }
+            }
+        }
+        spans.extend(blank(blanks));
+
+        (spans, width + blanks)
+    }
+}
+
+fn add_line<'a>(out: &mut LaidOut<'a>, mut line: Line<'a>, prefix: Vec<Span<'static>>, hanging: Vec<Span<'static>>, indent: usize, source: Option<SourceSpan>) {
+    line.spans.splice(0..0, prefix);
+
+    out.lines.push(line);
+    out.sources.push(source);
+    out.layouts.push(LineLayout { indent, hanging });
+}
+
+fn blank(width: usize) -> Option<Span<'static>> {
+    match width {
+        0 => None,
+        _ => Some(Span::raw(" ".repeat(width))),
+    }
+}
+
+// The lines as they are drawn in an area `width` columns wide: rules and
+// tables are fitted to it, and text too long for it is wrapped inside the
+// blocks it is in, with the lines it wraps onto indented like the first.
+pub(crate) fn fit_lines<'a>(lines: &[Line<'a>], layouts: &[LineLayout], rules: &[RuleLine], tables: &[TableLines<'a>], width: usize) -> Vec<Line<'a>> {
+    let mut lines = lines.to_vec();
+    let indent = |line: usize| layouts.get(line).map_or(0, |layout| layout.indent);
+
+    for rule in rules {
+        if let Some(line) = lines.get_mut(rule.line) {
+            let indent = indent(rule.line);
+            let rule_width = rule.rule.width(rule.text_width, Some(width.saturating_sub(indent)));
+
+            line.spans = split_at_width(&line.spans, indent).0;
+            line.spans.push(Span::raw(rule.rule.symbol.to_string().repeat(rule_width)));
+        }
+    }
+
+    let mut fitted = Vec::new();
+    let mut tables = tables.iter().peekable();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let layout = layouts.get(i);
+
+        match tables.next_if(|table| table.line == i) {
+            Some(table) => {
+                let indent = indent(i);
+                let (prefix, _) = split_at_width(&lines[i].spans, indent);
+                let hanging = layout.map(|layout| layout.hanging.clone()).unwrap_or_default();
+
+                for (row, mut line) in table.table.lines(width.saturating_sub(indent)).into_iter().enumerate() {
+                    let prefix = match row {
+                        0 => prefix.clone(),
+                        _ => hanging.clone(),
+                    };
+                    line.spans.splice(0..0, prefix);
+                    fitted.push(line.style(lines[i].style));
+                }
+                i += table.lines.max(1);
+            },
+            None => {
+                fitted.extend(wrap_line(lines[i].clone(), layout, width));
+                i += 1;
+            },
+        }
+    }
+
+    fitted
+}
+
+fn wrap_line<'a>(line: Line<'a>, layout: Option<&LineLayout>, width: usize) -> Vec<Line<'a>> {
+    let indent = layout.map_or(0, |layout| layout.indent);
+    let aligned = matches!(line.alignment, Some(Alignment::Center | Alignment::Right));
+    let available = width.saturating_sub(indent);
+
+    if available == 0 || (line.width() <= width && (indent == 0 || !aligned)) {
+        return vec![line];
+    }
+
+    let (prefix, text) = split_at_width(&line.spans, indent);
+    let hanging = layout.map(|layout| layout.hanging.clone()).unwrap_or_default();
+
+    wrap_spans(&text, available).into_iter().enumerate().map(|(i, row)| {
+        let mut spans = match i {
+            0 => prefix.clone(),
+            _ => hanging.clone(),
+        };
+
+        // Alignment is within the blocks, so the indent has to stay put.
+        let mut alignment = line.alignment;
+        if indent > 0 && aligned {
+            let room = available.saturating_sub(row.iter().map(Span::width).sum());
+            let offset = match line.alignment {
+                Some(Alignment::Center) => room / 2,
+                _ => room,
+            };
+            spans.extend(blank(offset));
+            alignment = Some(Alignment::Left);
+        }
+
+        spans.extend(row);
+        Line { spans, style: line.style, alignment }
+    }).collect()
+}
+
+// Splits `spans` into rows at most `width` columns wide, breaking between
+// words where it can. The spaces a row is broken at are left out.
+fn wrap_spans(spans: &[Span], width: usize) -> Vec<Vec<Span<'static>>> {
+    let chars: Vec<(char, Style)> = spans.iter()
+        .flat_map(|span| span.content.chars().map(|c| (c, span.style)))
+        .collect();
+    let is_space = |c: char| c == ' ' || c == '\t';
+
+    let mut rows: Vec<Vec<(char, Style)>> = vec![Vec::new()];
+    let mut row_width = 0;
+
+    for word in chars.chunk_by(|a, b| is_space(a.0) == is_space(b.0)) {
+        let word_width: usize = word.iter().map(|(c, _)| c.width().unwrap_or(0)).sum();
+        let wrapped = rows.len() > 1;
+        let row = rows.last_mut().expect("there is always a row");
+
+        if is_space(word[0].0) {
+            // Rows that are wrapped onto don't start with spaces.
+            if row.is_empty() && wrapped {
+                continue;
+            }
+            if row_width + word_width <= width {
+                row.extend_from_slice(word);
+                row_width += word_width;
+            } else if row_width > 0 {
+                rows.push(Vec::new());
+                row_width = 0;
+            }
+            continue;
+        }
+
+        if row_width > 0 && row_width + word_width > width {
+            while row.last().is_some_and(|(c, _)| is_space(*c)) {
+                row.pop();
+            }
+            rows.push(Vec::new());
+            row_width = 0;
+        }
+
+        // Words longer than a row are broken wherever they reach its end.
+        for &(c, style) in word {
+            let char_width = c.width().unwrap_or(0);
+            if row_width > 0 && row_width + char_width > width {
+                rows.push(Vec::new());
+                row_width = 0;
+            }
+            rows.last_mut().expect("there is always a row").push((c, style));
+            row_width += char_width;
+        }
+    }
+
+    rows.into_iter().map(|row| {
+        let mut spans: Vec<Span<'static>> = Vec::new();
+        for (c, style) in row {
+            match spans.last_mut() {
+                Some(span) if span.style == style => span.content.to_mut().push(c),
+                _ => spans.push(Span::styled(c.to_string(), style)),
+            }
+        }
+        spans
+    }).collect()
+}
+
+// Splits `spans` after their first `width` columns.
+fn split_at_width<'a>(spans: &[Span<'a>], width: usize) -> (Vec<Span<'a>>, Vec<Span<'a>>) {
+    let mut head = Vec::new();
+    let mut tail = Vec::new();
+    let mut taken = 0;
+
+    for span in spans {
+        let span_width = span.content.width();
+
+        if taken >= width {
+            tail.push(span.clone());
+        } else if taken + span_width <= width {
+            head.push(span.clone());
+            taken += span_width;
+        } else {
+            let mut end = 0;
+            for (i, c) in span.content.char_indices() {
+                if taken >= width {
+                    break;
+                }
+                taken += c.width().unwrap_or(0);
+                end = i + c.len_utf8();
+            }
+            head.push(Span::styled(span.content[..end].to_string(), span.style));
+            tail.push(Span::styled(span.content[end..].to_string(), span.style));
+        }
+    }
+
+    (head, tail)
+}
+
+#[cfg(test)]
+mod test {
+    use ratatui::{style::Stylize, text::{Line, Span}, widgets::{Paragraph, Wrap}};
+
+    use crate::tuihtml::parser::construct_widget;
+
+    #[test]
+    fn blocks_are_indented_and_their_margins_collapse() {
+        let html = "<p>a</p><blockquote><p>quoted</p><div>b<p>c</p> </div></blockquote><ul><li>one<ul><li>two</ul><li></ul>";
+
+        let widget = construct_widget(html);
+
+        assert_eq!(widget.paragraph, Paragraph::new(vec![
+            Line::from(vec![Span::from("a")]),
+            // The paragraph's margin and the quote's are one line.
+            Line::raw("\u{00A0}"),
+            Line::from(vec![Span::from("\u{2502}").dark_gray(), Span::raw(" "), Span::from("quoted").italic()]),
+            Line::from(vec![Span::from("\u{2502}").dark_gray(), Span::raw(" "), Span::raw("\u{00A0}")]),
+            Line::from(vec![Span::from("\u{2502}").dark_gray(), Span::raw(" "), Span::from("b").italic()]),
+            Line::from(vec![Span::from("\u{2502}").dark_gray(), Span::raw(" "), Span::from("c").italic()]),
+            Line::raw("\u{00A0}"),
+            Line::from(vec![Span::raw("  "), Span::from("\u{2022} "), Span::from("one")]),
+            Line::from(vec![Span::raw("      "), Span::from("\u{2022} "), Span::from("two")]),
+            Line::from(vec![Span::raw("    "), Span::raw("\u{00A0}")]),
+            Line::from(vec![Span::raw("  "), Span::from("\u{2022} ")]),
+            Line::raw("\u{00A0}"),
+        ]).wrap(Wrap { trim: false }));
+        assert_eq!(widget.line_layouts.iter().map(|layout| layout.indent).collect::<Vec<_>>(), vec![0, 0, 2, 2, 2, 2, 0, 4, 8, 4, 4, 0]);
+    }
+
+    #[test]
+    fn margins_at_the_start_collapse_into_the_top_edge() {
+        let quote = || Line::from(vec![Span::from("\u{2502}").dark_gray(), Span::raw(" "), Span::from("q").italic()]);
+
+        for html in ["<blockquote>q</blockquote>", "<p></p> <div><blockquote>q</blockquote></div>"] {
+            let widget = construct_widget(html);
+
+            assert_eq!(widget.lines, vec![quote(), Line::raw("\u{00A0}")], "{}", html);
+        }
+    }
+
+    #[test]
+    fn text_wraps_inside_its_blocks() {
+        let html = "<ul><li>one two three four</li></ul><blockquote><h1>Title</h1></blockquote>";
+
+        let widget = construct_widget(html);
+
+        assert_eq!(widget.get_paragraph_for_width(12), Paragraph::new(vec![
+            Line::from(vec![Span::raw("  "), Span::from("\u{2022} "), Span::from("one two")]),
+            Line::from(vec![Span::raw("    "), Span::from("three")]),
+            Line::from(vec![Span::raw("    "), Span::from("four")]),
+            Line::raw("\u{00A0}"),
+            // Centered between the quote's gutter and the right edge.
+            Line::from(vec![Span::from("\u{2502}").dark_gray(), Span::raw(" "), Span::raw("  "), Span::from("Title").bold().italic()]).left_aligned(),
+            Line::from(vec![Span::from("\u{2502}").dark_gray(), Span::raw(" "), Span::raw("\u{00AF}".repeat(10))]).left_aligned(),
+            Line::raw("\u{00A0}"),
+        ]).wrap(Wrap { trim: false }));
+
+        // Words longer than the width are broken.
+        let widget = construct_widget("<p>abcdefgh ij</p>");
+        assert_eq!(widget.get_paragraph_for_width(5), Paragraph::new(vec![
+            Line::from(vec![Span::from("abcde")]),
+            Line::from(vec![Span::from("fgh")]),
+            Line::from(vec![Span::from("ij")]),
+            Line::raw("\u{00A0}"),
+        ]).wrap(Wrap { trim: false }));
+    }
+
+    #[test]
+    fn quotes_have_a_gutter_and_an_attribution() {
+        let html = concat!(
+            r#"<blockquote cite="https://a.example"><p>one two three</p><blockquote>inner</blockquote></blockquote>"#,
+            "<blockquote>said <cite>Someone</cite> <!-- end --></blockquote>",
+            // Only a <cite> at the end is the attribution.
+            "<blockquote><cite>Book</cite> says</blockquote>",
+        );
+        let gutter = || Span::from("\u{2502}").dark_gray();
+
+        let widget = construct_widget(html);
+
+        assert_eq!(widget.get_paragraph_for_width(11), Paragraph::new(vec![
+            Line::from(vec![gutter(), Span::raw(" "), Span::from("one two").italic()]),
+            Line::from(vec![gutter(), Span::raw(" "), Span::from("three").italic()]),
+            Line::from(vec![gutter(), Span::raw(" "), Span::raw("\u{00A0}")]),
+            Line::from(vec![gutter(), Span::raw(" "), gutter(), Span::raw(" "), Span::from("inner").italic()]),
+            Line::from(vec![gutter(), Span::raw(" "), Span::raw("\u{00A0}")]),
+            Line::from(vec![gutter(), Span::raw(" "), Span::from("\u{2014}").not_italic()]),
+            Line::from(vec![gutter(), Span::raw(" "), Span::from("https://a").not_italic()]),
+            Line::from(vec![gutter(), Span::raw(" "), Span::from(".example").not_italic()]),
+            Line::raw("\u{00A0}"),
+            Line::from(vec![gutter(), Span::raw(" "), Span::from("said ").italic()]),
+            Line::from(vec![gutter(), Span::raw(" "), Span::from("\u{2014} ").not_italic(), Span::from("Someone").not_italic()]),
+            Line::raw("\u{00A0}"),
+            Line::from(vec![gutter(), Span::raw(" "), Span::from("Book").italic(), Span::from(" says").italic()]),
+            Line::raw("\u{00A0}"),
+        ]).wrap(Wrap { trim: false }));
+    }
+}
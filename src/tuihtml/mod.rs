@@ -1,4 +1,20 @@
+pub mod color;
+pub mod css;
+pub mod diagnostics;
+pub mod dom;
+pub mod encoding;
+pub mod entities;
+pub mod error;
 pub mod html;
+pub mod input;
+pub mod layout;
 pub mod tokenizer;
+pub mod options;
 pub mod parser;
+pub mod selector;
+pub mod source;
+pub mod stream;
+pub mod table;
+pub mod theme;
+pub mod treebuilder;
 pub mod widget;
@@ -0,0 +1,47 @@
+// Limits for parsing HTML that may be hostile. HtmlWidget::build, and so
+// HtmlWidget::new, stops at a limit, keeping what was parsed up to there,
+// and reports which limits were hit. The try_ constructors fail with an
+// Error instead when any of them is exceeded. HtmlStream stops at them too,
+// and reports them from finish. Everything else keeps to the default depth
+// and attribute limits, but reads all of the input and lays out all of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseOptions {
+    // How deeply elements may nest. Deeper elements are left out.
+    pub max_depth: usize,
+    // The longest input accepted, in bytes.
+    pub max_document_size: usize,
+    // How many tokens are read before the rest of the input is ignored.
+    pub max_tokens: usize,
+    // Attributes kept per element, the rest are dropped.
+    pub max_attributes: usize,
+    // Lines of output kept, before the render area wraps them.
+    pub max_lines: usize,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            max_depth: 512,
+            max_document_size: 16 * 1024 * 1024,
+            max_tokens: 1_000_000,
+            max_attributes: 256,
+            max_lines: 100_000,
+        }
+    }
+}
+
+impl ParseOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+// The ParseOptions limits, for reporting which ones cut a document short.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Limit {
+    Depth,
+    DocumentSize,
+    Tokens,
+    Attributes,
+    Lines,
+}
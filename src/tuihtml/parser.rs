@@ -1,5 +1,7 @@
-use ratatui::{style::{Modifier, Style, Stylize}, text::{Line, Span}, widgets::{Paragraph, Wrap}};
-use crate::tuihtml::{html::*, tokenizer::{HtmlTokenizer, Token}, widget::{HtmlWidget}};
+use std::io::{self, Read};
+
+use ratatui::{style::Style, text::{Line, Span}, widgets::{Paragraph, Wrap}};
+use crate::tuihtml::{color::Palette, css::{InlineStyle, StyleSheet}, diagnostics::ParseError, dom::{Document, NodeData, NodeEdge, NodeId}, encoding::{decode_html, try_decode_html}, error::{Error, Result}, html::*, layout::{BlockFlow, LaidOut, LineLayout}, options::{Limit, ParseOptions}, source::SourceSpan, table::{Table, TableLines, BLANK_BORDER}, theme::{BlockStyle, ElementStyle, RuleLine, TableBorder, Theme, FALLBACK_WIDTH}, tokenizer::{tokenize, HtmlTokenizer, Token}, treebuilder::TreeBuilder, widget::{HtmlWidget}};
 
 #[derive(Default, Debug, Clone, PartialEq)]
 pub enum ListState {
@@ -11,241 +13,471 @@ pub enum ListState {
 #[derive(Default, Debug, Clone, PartialEq)]
 pub struct StyleContext {
     list_state: Vec<ListState>,
-    list_index: Vec<usize>,
+    list_index: Vec<i64>,
     link_list: Vec<String>,
     link_index: usize,
     img_list: Vec<String>,
     img_index: usize,
-    active_modifiers: Vec<Modifier>,
     active_styles: Vec<Style>,
+    // The visibility set by the elements the walk is in, innermost last.
+    visibility: Vec<bool>,
+    palette: Palette,
 }
 
 impl StyleContext {
-    pub fn new() -> Self {
-        Self {
-            list_state: Vec::new(),
-            list_index: Vec::new(),
-            link_list: Vec::new(),
-            link_index: 0,
-            img_list: Vec::new(),
-            img_index: 0,
-            active_modifiers: Vec::new(),
-            active_styles: Vec::new(),
-        }
-    }
-
-    pub fn remove_modifiers(&mut self, tag: HtmlTag) {
-        let modifiers = tag.to_modifiers();
-        for modifier in modifiers {
-            if let Some(i) = self.active_modifiers.iter().position(|m| *m == modifier) {
-                self.active_modifiers.remove(i);
-            }
-
+    pub fn push_style(&mut self, style: Style) {
+        if style != Style::default() {
+            self.active_styles.push(style);
         }
     }
 
-    pub fn add_modifiers(&mut self, tag: HtmlTag) {
-        for modifier in tag.to_modifiers() {
-            self.active_modifiers.push(modifier);
+    pub fn pop_style(&mut self, style: Style) {
+        if let Some(i) = self.active_styles.iter().rposition(|s| *s == style) {
+            self.active_styles.remove(i);
         }
     }
 
+    // The style text gets here, fitted to the terminal.
+    pub fn current_style(&self) -> Style {
+        self.palette.adapt(self.authored_style())
+    }
+
+    fn authored_style(&self) -> Style {
+        self.active_styles.iter().fold(Style::default(), |style, active| style.patch(*active))
+    }
+
     pub fn construct_span<'a>(&self, text: String) -> Span<'a> {
-        let mut span = Span::from(text);
+        self.hide(Span::styled(text, self.current_style()))
+    }
 
-        for modifier in &self.active_modifiers {
-            span.style = span.style.add_modifier(*modifier);
-        }
+    // A theme's prefix or suffix, in the current style patched with its own.
+    pub fn decorate<'a>(&self, span: &Span<'static>) -> Span<'a> {
+        self.hide(Span::styled(span.content.to_string(), self.palette.adapt(self.authored_style().patch(span.style))))
+    }
 
-        for style in &self.active_styles {
-            span.style = span.style.patch(*style);
+    // Anything drawn inside a hidden element, text or not, is blanks as
+    // wide as it would have been.
+    pub fn hide<'a>(&self, span: Span<'a>) -> Span<'a> {
+        match self.hidden() {
+            true => Span::raw(" ".repeat(span.width())),
+            false => span,
         }
+    }
 
-        span
+    fn hidden(&self) -> bool {
+        self.visibility.last() == Some(&false)
     }
 
     pub fn styled(&self) -> bool {
-        if self.active_modifiers.is_empty() && self.active_styles.is_empty() {
-            return false
-        }
+        !self.active_styles.is_empty()
+    }
 
-        true
+    // Lists are indented by the blocks they are laid out in, not by the
+    // marker.
+    fn list_marker(&self) -> Option<String> {
+        match self.list_state.last()? {
+            ListState::Ordered => Some(format!("{}. ", self.list_index.last().unwrap_or(&0_i64))),
+            ListState::Unordered => Some(String::from("\u{2022} ")),
+        }
     }
 }
 
-pub fn get_html_style<'a>(tag: &HtmlTag, mut spans: Vec<Span<'a>>, context: &StyleContext) -> (Vec<Span<'a>>, Vec<Line<'a>>) {
-    const HR_WIDTH: usize = 120;
-
+// Turns the spans gathered inside an element into lines once it ends, as
+// `element_style` says, also returning which of the lines are rules. Spans
+// that are not made into lines are handed back to go on with the text after
+// the element.
+pub fn get_html_style<'a>(tag: &HtmlTag, element_style: &ElementStyle, mut spans: Vec<Span<'a>>, context: &StyleContext, theme: &Theme) -> (Vec<Span<'a>>, Vec<Line<'a>>, Vec<RuleLine>) {
     match tag {
-        HtmlTag::H1 => {
-            let spans_width: usize = spans.iter().map(|span| span.width()).collect::<Vec<usize>>().iter().sum();
-            let header_spacing = vec![Span::from(" ".repeat(spans_width / 2))];
-            let header_line = Line::from([header_spacing.clone(),
-                spans.clone(),
-                header_spacing].concat());
-            let overscore_line = Line::from(Span::from("\u{00AF}".repeat(spans_width * 2)));
-
-            let lines = vec![header_line.centered(), overscore_line.centered()];
-            (Vec::new(), lines)
-        },
-        HtmlTag::HR => {
-            let overscore_line = Line::from(Span::from("\u{00AF}".repeat(HR_WIDTH)));
-            let underscore_line = Line::from(Span::from("\u{005F}".repeat(HR_WIDTH)));
-
-            let lines = vec![underscore_line.centered(), overscore_line.centered()];
-            (spans, lines)
-        },
-        HtmlTag::LI => {
-            match context.list_state.last() {
-                Some(last_state) => {
-                    match last_state {
-                        ListState::Ordered => {
-                            let list_item = Line::from(
-                                [vec![Span::from(format!(
-                                        "{}{}. ",
-                                        " ".repeat(2 * context.list_state.len()),
-                                        context.list_index.last()
-                                        .unwrap_or(&0_usize)
-                                        .clone()
-                                ))],
-                                spans.clone()].concat()
-                            );
-                            (Vec::new(), vec![list_item])
-                        },
-                        ListState::Unordered => {
-                            let list_item = Line::from(
-                                [vec![Span::from(format!(
-                                        "{}\u{2022} ",
-                                        " ".repeat(2 * context.list_state.len()),
-                                ))],
-                                spans.clone()].concat()
-                            );
-                            (Vec::new(), vec![list_item])
-                        }
-                    }
-                },
-                None => {
-                    (spans, Vec::new())
-                }
-            }
-        },
+        HtmlTag::BR => return (Vec::new(), vec![Line::from(spans)], Vec::new()),
         HtmlTag::A => {
-            spans.push(Span::from(format!("[{}]", context.link_index)).bold());
-            (spans, Vec::new())
+            if let Some(style) = theme.link_marker {
+                spans.push(context.hide(Span::styled(format!("[{}]", context.link_index), context.palette.adapt(style))));
+            }
         },
-        HtmlTag::H2 => {
-            let spans_width: usize = spans.iter().map(|span| span.width()).collect::<Vec<usize>>().iter().sum();
-            let above_line = Line::from(Span::from("\u{00A0}".repeat(spans_width)));
-            let header_line = Line::from(spans.clone());
-
-            let lines = vec![above_line, header_line.underlined()];
-            (Vec::new(), lines)
-        }
-        HtmlTag::H3 |
-        HtmlTag::H4 |
-        HtmlTag::H5 |
-        HtmlTag::H6 => {
-            let spans_width: usize = spans.iter().map(|span| span.width()).collect::<Vec<usize>>().iter().sum();
-            let above_line = Line::from(Span::from("\u{00A0}".repeat(spans_width)));
-            let header_line = Line::from(spans.clone());
-
-            let lines = vec![above_line, header_line];
-            (Vec::new(), lines)
+        // The layout puts the marker of a block list item on its first line.
+        HtmlTag::LI if element_style.block.is_none() => match context.list_marker() {
+            Some(marker) => spans.insert(0, context.hide(Span::from(marker))),
+            None => return (spans, Vec::new(), Vec::new()),
         },
-        HtmlTag::NAV |
-        HtmlTag::DIV |
-        HtmlTag::OL |
-        HtmlTag::UL |
-        HtmlTag::P => {
-            match spans.is_empty() {
-                true => {
-                    (spans, Vec::new())
-                },
-                false => {
-                    let line = Line::from(spans.clone());
-                    (Vec::new(), vec![line])
+        _ => {}
+    }
+
+    match &element_style.block {
+        Some(block) => {
+            let (mut lines, mut rules) = block.lines(spans);
+            // Hidden rules still take up their lines.
+            if context.hidden() {
+                for rule in rules.drain(..) {
+                    lines[rule.line] = Line::raw("\u{00A0}");
                 }
             }
+
+            let lines = lines.into_iter().map(|line| {
+                let style = context.palette.adapt(line.style);
+                line.style(style)
+            }).collect();
+            (Vec::new(), lines, rules)
         },
-        _ => (spans, Vec::new())
+        None => (spans, Vec::new(), Vec::new()),
     }
 }
 
-pub fn is_self_closing(tag: &HtmlTag) -> bool {
-    match tag {
-        HtmlTag::HR => true,
-        HtmlTag::BR => true,
-        _ => false
+pub(crate) fn parse_document(html: &str) -> Document {
+    build_document(&mut HtmlTokenizer::new(html.chars()), false).0
+}
+
+// Like parse_document(), also returning every parse error met on the way,
+// in source order.
+pub(crate) fn parse_document_with_diagnostics(html: &str) -> (Document, Vec<ParseError>) {
+    build_document(&mut HtmlTokenizer::new(html.chars()), true)
+}
+
+// Builds the document from UTF-8 read incrementally from `reader`. Tokens
+// go straight into the tree as they are read rather than being collected
+// first.
+pub(crate) fn parse_document_from_reader(reader: impl Read) -> io::Result<Document> {
+    let mut tokenizer = HtmlTokenizer::from_reader(reader);
+    let (document, _) = build_document(&mut tokenizer, false);
+
+    match tokenizer.take_io_error() {
+        Some(error) => Err(error),
+        None => Ok(document),
+    }
+}
+
+// Builds a document from all of the input, nested and with attributes no
+// more than the default ParseOptions allow, which keeps deeply nested markup
+// from taking quadratic time.
+fn build_document(tokenizer: &mut HtmlTokenizer, diagnostics: bool) -> (Document, Vec<ParseError>) {
+    let options = ParseOptions::default();
+
+    let mut tree_builder = TreeBuilder::new();
+    tree_builder.set_max_depth(options.max_depth);
+    tokenizer.set_max_attributes(options.max_attributes);
+    if diagnostics {
+        tree_builder.enable_diagnostics();
+        tokenizer.enable_diagnostics();
+    }
+
+    loop {
+        let (token, span) = tokenizer.next_with_span();
+        let eof = token == Token::Eof;
+
+        tree_builder.process(token, span);
+
+        if eof {
+            break;
+        }
+    }
+
+    let mut errors = tokenizer.take_errors();
+    let (document, tree_errors) = tree_builder.finish_with_errors();
+    errors.extend(tree_errors);
+    errors.sort_by_key(|error| error.span.start.offset);
+
+    (document, errors)
+}
+
+// Parses `html` within the limits of `options`, failing when one of them is
+// exceeded rather than truncating.
+pub(crate) fn try_parse_document(html: &str, options: &ParseOptions) -> Result<Document> {
+    check_document_size(html.len(), options)?;
+    try_build_document(&mut tokenize(html), options)
+}
+
+// Parses `html` within the limits of `options`, keeping whatever was parsed
+// before a limit was hit. Also returns the limits that were hit.
+pub(crate) fn parse_document_with_options(html: &str, options: &ParseOptions) -> (Document, Vec<Limit>) {
+    let mut limits = Vec::new();
+
+    let mut end = html.len().min(options.max_document_size);
+    while !html.is_char_boundary(end) {
+        end -= 1;
+    }
+    if end < html.len() {
+        limits.push(Limit::DocumentSize);
+    }
+
+    // Only strict parsing fails.
+    let (document, hit) = build_bounded_document(&mut tokenize(&html[..end]), options, false).unwrap_or_default();
+    limits.extend(hit);
+
+    (document, limits)
+}
+
+pub(crate) fn try_parse_document_from_bytes(bytes: &[u8], hint: Option<&str>, options: &ParseOptions) -> Result<Document> {
+    check_document_size(bytes.len(), options)?;
+    try_parse_document(&try_decode_html(bytes, hint)?, options)
+}
+
+pub(crate) fn try_parse_document_from_reader(reader: impl Read, options: &ParseOptions) -> Result<Document> {
+    // One byte past the limit is enough to tell the input is too long.
+    let limit = options.max_document_size.saturating_add(1) as u64;
+    try_build_document(&mut HtmlTokenizer::from_reader(reader.take(limit)), options)
+}
+
+fn check_document_size(size: usize, options: &ParseOptions) -> Result<()> {
+    match size > options.max_document_size {
+        true => Err(Error::DocumentTooLarge { size, limit: options.max_document_size }),
+        false => Ok(()),
+    }
+}
+
+fn try_build_document(tokenizer: &mut HtmlTokenizer, options: &ParseOptions) -> Result<Document> {
+    let (document, _) = build_bounded_document(tokenizer, options, true)?;
+
+    match tokenizer.take_io_error() {
+        Some(error) => Err(error.into()),
+        None => Ok(document),
     }
 }
 
-fn parse_html(html: String) -> Vec<Token> {
+// Builds a document within the limits of `options`. When `strict` is set,
+// exceeding any of them is an error; otherwise parsing stops at the size and
+// token limits and leaves out elements and attributes past the others.
+fn build_bounded_document(tokenizer: &mut HtmlTokenizer, options: &ParseOptions, strict: bool) -> Result<(Document, Vec<Limit>)> {
+    let mut tree_builder = TreeBuilder::new();
+    tree_builder.set_max_depth(options.max_depth);
+    tokenizer.set_max_attributes(options.max_attributes);
 
-    let mut tokens = Vec::new();
-    let mut tokenizer = HtmlTokenizer::new(html.chars());
+    let mut limits = Vec::new();
+    let mut tokens = 0;
 
-    let mut current_token = tokenizer.next();
-    tokens.push(current_token.clone());
+    loop {
+        let (token, span) = tokenizer.next_with_span();
+        let eof = token == Token::Eof;
 
+        if span.end.offset > options.max_document_size {
+            if strict {
+                check_document_size(span.end.offset, options)?;
+            }
+            limits.push(Limit::DocumentSize);
+            break;
+        }
+        if !eof && tokens == options.max_tokens {
+            if strict {
+                return Err(Error::TooManyTokens { limit: options.max_tokens });
+            }
+            limits.push(Limit::Tokens);
+            break;
+        }
+        tokens += 1;
+
+        tree_builder.process(token, span);
+
+        if let Some(span) = tree_builder.depth_exceeded().filter(|_| strict) {
+            return Err(Error::NestingTooDeep { limit: options.max_depth, span });
+        }
+        if strict && tokenizer.attributes_dropped() {
+            return Err(Error::TooManyAttributes { limit: options.max_attributes });
+        }
+        if eof {
+            break;
+        }
+    }
 
-    while current_token != Token::Eof {
-        current_token = tokenizer.next();
-        tokens.push(current_token.clone());
+    if tree_builder.depth_exceeded().is_some() {
+        limits.push(Limit::Depth);
+    }
+    if tokenizer.attributes_dropped() {
+        limits.push(Limit::Attributes);
     }
 
-    tokens
+    Ok((tree_builder.finish(), limits))
 }
 
-pub fn construct_widget<'a>(html: String) -> HtmlWidget<'a> {
+pub(crate) fn construct_widget<'a>(html: &str) -> HtmlWidget<'a> {
+    construct_widget_from_document(parse_document(html))
+}
+
+// Decodes `bytes` in the encoding named by its byte order mark, `hint` or a
+// <meta> in the document, in that order, before building the widget.
+pub(crate) fn construct_widget_from_bytes<'a>(bytes: &[u8], hint: Option<&str>) -> HtmlWidget<'a> {
+    construct_widget(&decode_html(bytes, hint))
+}
 
-    let tokens = parse_html(html);
-    let mut lines: Vec<Line<'a>> = Vec::new();
-    let mut spans: Vec<Span<'a>> = Vec::new();
-    let mut style_context = StyleContext::new();
-    let mut element_stack: Vec<HtmlElement> = Vec::new();
+pub(crate) fn construct_widget_from_reader<'a>(reader: impl Read) -> io::Result<HtmlWidget<'a>> {
+    parse_document_from_reader(reader).map(construct_widget_from_document)
+}
+
+// Like construct_widget(), within the limits of `options`. Also returns the
+// limits that cut the document short.
+pub(crate) fn construct_widget_with_options<'a>(html: &str, theme: &Theme, options: &ParseOptions) -> (HtmlWidget<'a>, Vec<Limit>) {
+    let (document, mut limits) = parse_document_with_options(html, options);
+    let (widget, cut) = lay_out_document(document, theme, options.max_lines);
+    if cut {
+        limits.push(Limit::Lines);
+    }
+
+    (widget, limits)
+}
+
+// Lays out a document from one of the try_ functions, failing if it comes
+// to more lines than `options` allow.
+pub(crate) fn try_construct_widget_from_document<'a>(document: Document, theme: &Theme, options: &ParseOptions) -> Result<HtmlWidget<'a>> {
+    match lay_out_document(document, theme, options.max_lines) {
+        (_, true) => Err(Error::TooManyLines { limit: options.max_lines }),
+        (widget, false) => Ok(widget),
+    }
+}
 
-    for token in tokens {
+// Lays out at most `max_lines` lines of `document`, also returning whether
+// there were more.
+fn lay_out_document<'a>(document: Document, theme: &Theme, max_lines: usize) -> (HtmlWidget<'a>, bool) {
+    let mut layout = Layout::new(theme.clone());
 
-        // Skip whitespace tokens when not inside a styled context or a
-        // block-level text container. In a TUI we may not know what styling
-        // applies, so we collapse extraneous whitespace for clarity —
-        // except inside <p>, <div>, <section>, and <article> where spacing
-        // is semantically meaningful.
-        if Token::is_whitespace(&token) &&
-            !style_context.styled() &&
-            (element_stack.last().is_none_or(|el| el.tag != HtmlTag::P && el.tag != HtmlTag::DIV && el.tag != HtmlTag::SECTION && el.tag != HtmlTag::ARTICLE)) {
-            continue;
+    for edge in document.traverse(document.root()) {
+        layout.visit(&document, edge);
+        if layout.lines().len() > max_lines {
+            break;
         }
+    }
 
-        match token {
-            Token::Element(element) => {
-                match &element.closing {
-                    true => {
-                        if let Some(removed_element) = element_stack.pop() {
-                            let (new_spans, new_lines) = get_html_style(&removed_element.tag, spans, &style_context);
-                            lines = [lines, new_lines].concat();
-                            spans = new_spans;
+    layout.flush();
+    let cut = layout.lines().len() > max_lines;
+    if cut {
+        layout.truncate(max_lines);
+    }
 
-                            style_context.remove_modifiers(removed_element.tag);
+    (layout.into_widget(document), cut)
+}
 
-                            match &element.tag {
-                                HtmlTag::OL | HtmlTag::UL => {
-                                    lines.push(Line::raw("\u{00A0}"));
-                                    style_context.list_state.pop();
-                                    style_context.list_index.pop();
-                                },
-                                HtmlTag::P => {
-                                    lines.push(Line::raw("\u{00A0}"));
-                                },
-                                _ => {}
-                            }
+pub(crate) fn construct_widget_with_diagnostics<'a>(html: &str) -> (HtmlWidget<'a>, Vec<ParseError>) {
+    let (document, errors) = parse_document_with_diagnostics(html);
+    (construct_widget_from_document(document), errors)
+}
+
+pub(crate) fn construct_widget_from_document<'a>(document: Document) -> HtmlWidget<'a> {
+    construct_widget_from_document_with_theme(document, &Theme::default())
+}
+
+pub(crate) fn construct_widget_from_document_with_theme<'a>(document: Document, theme: &Theme) -> HtmlWidget<'a> {
+    let mut layout = Layout::new(theme.clone());
+
+    for edge in document.traverse(document.root()) {
+        layout.visit(&document, edge);
+    }
+
+    layout.into_widget(document)
+}
+
+// Turns a walk over the document into lines. All of the state lives here so
+// a walk can be paused and picked up again, which lets HtmlStream lay out
+// only the part of a growing document that changed.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Layout<'a> {
+    out: LaidOut<'a>,
+    state: LayoutState<'a>,
+    theme: Theme,
+}
+
+#[derive(Debug, Clone, Default)]
+struct LayoutState<'a> {
+    spans: Vec<Span<'a>>,
+    style_context: StyleContext,
+    element_stack: Vec<OpenElement>,
+    // Source span of the spans not yet made into a line.
+    pending_source: Option<SourceSpan>,
+    // An element with display: none, whose subtree is being skipped.
+    skipping: Option<NodeId>,
+    // The rules of the <style> elements walked past. Like a browser showing
+    // a page that is still loading, a rule only styles what comes after it.
+    stylesheet: StyleSheet,
+    flow: BlockFlow,
+    // A quote whose <cite> was laid out as its attribution, which it stops
+    // being if more of a growing document ends up after it.
+    attributed: Option<NodeId>,
+}
+
+// An element the walk is inside of, with the style it was given at its
+// start tag. Its end tag undoes that style, even if the style sheet has
+// grown since.
+#[derive(Debug, Clone)]
+struct OpenElement {
+    tag: HtmlTag,
+    style: ElementStyle,
+    inline: InlineStyle,
+}
+
+// The cells of a <tr>, or of cells next to each other outside of one, with
+// the styles of the row group and the row.
+#[derive(Debug)]
+struct TableRow {
+    cells: Vec<NodeId>,
+    styles: [Style; 2],
+}
+
+// Everything needed to rewind a Layout to an earlier point of its walk.
+#[derive(Debug, Clone)]
+pub(crate) struct LayoutCheckpoint<'a> {
+    lines: usize,
+    state: LayoutState<'a>,
+}
+
+impl<'a> Layout<'a> {
+    pub(crate) fn new(theme: Theme) -> Self {
+        let mut layout = Layout::default();
+        layout.state.style_context.palette = theme.palette;
+        layout.theme = theme;
+        layout
+    }
+
+    // Starts the walk over, keeping the theme.
+    pub(crate) fn reset(&mut self) {
+        *self = Layout::new(std::mem::take(&mut self.theme));
+    }
+
+    pub(crate) fn checkpoint(&self) -> LayoutCheckpoint<'a> {
+        LayoutCheckpoint {
+            lines: self.out.lines.len(),
+            state: self.state.clone(),
+        }
+    }
+
+    pub(crate) fn restore(&mut self, checkpoint: &LayoutCheckpoint<'a>) {
+        self.truncate(checkpoint.lines);
+        self.state = checkpoint.state.clone();
+    }
+
+    pub(crate) fn visit(&mut self, document: &Document, edge: NodeEdge) {
+        // A table is laid out whole when the walk gets to it, and the walk
+        // then skips over what is inside.
+        if let (None, NodeEdge::Start(id)) = (self.state.skipping, edge) {
+            if let Some(element) = document.node(id).element().filter(|element| element.tag == HtmlTag::TABLE) {
+                let (element_style, inline) = resolve_style(&self.theme, &self.state.stylesheet, document, id, element);
+                if !inline.display_none {
+                    self.lay_out_table(document, id, &element_style, inline.visible);
+                    self.state.skipping = Some(id);
+                    return;
+                }
+            }
+        }
+
+        let LayoutState { spans, style_context, element_stack, pending_source, skipping, stylesheet, flow, attributed } = &mut self.state;
+        let out = &mut self.out;
+        let theme = &self.theme;
+
+        if let Some(skipped) = *skipping {
+            if edge == NodeEdge::End(skipped) {
+                *skipping = None;
+            }
+            return;
+        }
+
+        match edge {
+            NodeEdge::Start(id) => {
+                match &document.node(id).data {
+                    NodeData::Element(element) => {
+                        let (element_style, inline) = resolve_style(theme, stylesheet, document, id, element);
+                        if inline.display_none {
+                            *skipping = Some(id);
+                            return;
                         }
-                    }
-                    false => {
+
                         match &element.tag {
                             HtmlTag::OL => {
                                 style_context.list_state.push(ListState::Ordered);
-                                style_context.list_index.push(0);
+                                style_context.list_index.push(element.start().unwrap_or(1).saturating_sub(1));
                             },
                             HtmlTag::UL => {
                                 style_context.list_state.push(ListState::Unordered);
@@ -253,73 +485,504 @@ pub fn construct_widget<'a>(html: String) -> HtmlWidget<'a> {
                             }
                             HtmlTag::LI => {
                                 if let Some(index) = style_context.list_index.pop() {
-                                    let new_index = index + 1;
+                                    let new_index = element.ordinal().unwrap_or(index.saturating_add(1));
                                     style_context.list_index.push(new_index);
                                 }
                             },
                             HtmlTag::A => {
                                 style_context.link_index += 1;
-                                if let Some(link) = element.attributes.get("href") {
+                                if let Some(link) = element.href() {
                                     style_context.link_list.push(link.to_owned());
                                 }
                             }
                             HtmlTag::IMG => {
                                 style_context.img_index += 1;
-                                if let Some(image) = element.attributes.get("src") {
+                                if let Some(image) = element.src() {
                                     style_context.img_list.push(image.to_owned());
                                 }
                             }
+                            HtmlTag::CITE if theme.attribution.is_some() && is_attribution(document, id) => {
+                                *attributed = document.parent(id);
+                            }
                             _ => {}
                         }
 
-                        match is_self_closing(&element.tag) {
+                        // Before anything of the element is drawn, so that
+                        // its markers are hidden along with its text.
+                        if let Some(visible) = inline.visible {
+                            style_context.visibility.push(visible);
+                        }
+
+                        if let Some(block) = &element_style.block {
+                            flow.push_text(out, std::mem::take(spans), pending_source.take());
+
+                            flow.open(out, adapt_block(style_context, block), document.span(id));
+                            if element.tag == HtmlTag::LI {
+                                if let Some(marker) = style_context.list_marker() {
+                                    flow.set_marker(style_context.hide(Span::from(marker)));
+                                }
+                            }
+                        }
+
+                        style_context.push_style(element_style.style);
+                        if let Some(prefix) = &element_style.prefix {
+                            spans.push(style_context.decorate(prefix));
+                        }
+
+                        match element.is_empty() {
                             true => {
-                                let (new_spans, new_lines) = get_html_style(&element.tag, spans, &style_context);
-                                lines = [lines, new_lines].concat();
-                                spans = new_spans;
+                                if let Some(suffix) = &element_style.suffix {
+                                    spans.push(style_context.decorate(suffix));
+                                }
+                                style_context.pop_style(element_style.style);
+
+                                let (new_spans, new_lines, new_rules) = get_html_style(&element.tag, &element_style, std::mem::take(spans), style_context, theme);
+                                flow.push(out, new_lines, new_rules, Some(merge_source(*pending_source, document.span(id))));
+                                *spans = new_spans;
+                                if inline.visible.is_some() {
+                                    style_context.visibility.pop();
+                                }
+                                if element_style.block.is_some() {
+                                    flow.close(out, document.span(id));
+                                }
+
+                                if spans.is_empty() {
+                                    *pending_source = None;
+                                }
                             },
                             false => {
-                                style_context.add_modifiers(element.tag.clone());
-                                element_stack.push(element);
+                                element_stack.push(OpenElement { tag: element.tag.clone(), style: element_style, inline });
                             }
                         }
-                    }
+                    },
+                    NodeData::Text(text) => {
+                        if element_stack.last().is_some_and(|open| open.tag.is_hidden()) {
+                            return;
+                        }
+
+                        for (i, piece) in text.split('\n').enumerate() {
+                            if i > 0 && !spans.is_empty() {
+                                flow.push(out, vec![Line::from(std::mem::take(spans))], Vec::new(), pending_source.take());
+                            }
+
+                            // Skip whitespace when not inside a styled context or a
+                            // block-level text container. In a TUI we may not know what styling
+                            // applies, so we collapse extraneous whitespace for clarity —
+                            // except inside <p>, <div>, <section>, and <article> where spacing
+                            // is semantically meaningful.
+                            if piece.is_empty() || (piece.chars().all(char::is_whitespace) &&
+                                !style_context.styled() &&
+                                (element_stack.last().is_none_or(|open| !matches!(open.tag, HtmlTag::P | HtmlTag::DIV | HtmlTag::SECTION | HtmlTag::ARTICLE)))) {
+                                continue;
+                            }
+
+                            spans.push(style_context.construct_span(piece.to_string()));
+                            *pending_source = Some(merge_source(*pending_source, document.span(id)));
+                        }
+                    },
+                    NodeData::Document | NodeData::Comment(_) => {}
                 }
             },
-            Token::Text(text) => {
-                spans.push(style_context.construct_span(text));
-            },
-            Token::NewLine => {
-                if !spans.is_empty() {
-                    lines.push(Line::from(spans.clone()));
+            NodeEdge::End(id) => {
+                let Some(element) = document.node(id).element() else {
+                    return;
+                };
+
+                if element.is_empty() {
+                    return;
+                }
+
+                let Some(OpenElement { style: element_style, inline, .. }) = element_stack.pop() else {
+                    return;
+                };
+                // So is the whitespace after the last block inside this one.
+                let block = element_style.block.is_some();
+                if block && flow.has_blocks() && spans.iter().all(|span| span.content.trim().is_empty()) {
                     spans.clear();
                 }
+                if let Some(suffix) = &element_style.suffix {
+                    spans.push(style_context.decorate(suffix));
+                }
+
+                let (new_spans, new_lines, new_rules) = get_html_style(&element.tag, &element_style, std::mem::take(spans), style_context, theme);
+                flow.push(out, new_lines, new_rules, Some(merge_source(*pending_source, document.span(id))));
+                *spans = new_spans;
+                if spans.is_empty() {
+                    *pending_source = None;
+                }
+
+                if element.tag == HtmlTag::BLOCKQUOTE {
+                    match *attributed == Some(id) {
+                        true => *attributed = None,
+                        false => if let (Some(cite), Some(attribution)) = (element.attr("cite"), &theme.attribution) {
+                            // The source a quote only names in its cite
+                            // attribute gets a line like a <cite> would.
+                            flow.push_text(out, std::mem::take(spans), pending_source.take());
+
+                            let block = attribution.block.clone().unwrap_or_default();
+                            flow.open(out, adapt_block(style_context, &block), document.span(id));
+                            style_context.push_style(attribution.style);
+                            let mut line = Vec::from_iter(attribution.prefix.as_ref().map(|prefix| style_context.decorate(prefix)));
+                            line.push(style_context.construct_span(cite.to_owned()));
+                            style_context.pop_style(attribution.style);
+                            flow.push(out, vec![Line::from(line)], Vec::new(), Some(document.span(id)));
+                            flow.close(out, document.span(id));
+                        },
+                    }
+                }
+
+                style_context.pop_style(element_style.style);
+                if inline.visible.is_some() {
+                    style_context.visibility.pop();
+                }
+                if block {
+                    flow.close(out, document.span(id));
+                }
+
+                match &element.tag {
+                    HtmlTag::OL | HtmlTag::UL => {
+                        style_context.list_state.pop();
+                        style_context.list_index.pop();
+                    },
+                    HtmlTag::STYLE => stylesheet.append(StyleSheet::parse(&document.text_content(id))),
+                    _ => {}
+                }
             }
-            Token::Eof => {
-                if !spans.is_empty() {
-                    lines.push(Line::from(spans.clone()));
-                    spans.clear();
+        }
+    }
+
+    fn lay_out_table(&mut self, document: &Document, id: NodeId, element_style: &ElementStyle, visible: Option<bool>) {
+        let mut caption = None;
+        let (mut head, mut body, mut foot, mut fostered) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        let mut open_row = false;
+
+        for child in document.children(id) {
+            let tag = document.node(child).tag();
+            match tag {
+                Some(HtmlTag::CAPTION) => caption = Some(child),
+                Some(HtmlTag::THEAD | HtmlTag::TBODY | HtmlTag::TFOOT) => {
+                    let Some(element) = document.node(child).element() else {
+                        continue;
+                    };
+                    let (style, inline) = resolve_style(&self.theme, &self.state.stylesheet, document, child, element);
+                    if inline.display_none {
+                        continue;
+                    }
+
+                    // Header rows go first and footer rows last, wherever
+                    // they are in the markup.
+                    let rows = match tag {
+                        Some(HtmlTag::THEAD) => &mut head,
+                        Some(HtmlTag::TFOOT) => &mut foot,
+                        _ => &mut body,
+                    };
+                    let mut open_group_row = false;
+                    for row in document.children(child) {
+                        self.sort_into_rows(document, row, style.style, rows, &mut open_group_row, &mut fostered);
+                    }
+                },
+                Some(HtmlTag::COL | HtmlTag::COLGROUP) => {},
+                _ => {
+                    self.sort_into_rows(document, child, Style::default(), &mut body, &mut open_row, &mut fostered);
+                    continue;
+                },
+            }
+            open_row = false;
+        }
+
+        // Like browsers, whatever is in the table without being part of it
+        // goes before it.
+        for child in fostered {
+            for edge in document.traverse(child) {
+                self.visit(document, edge);
+            }
+        }
+
+        if let Some(visible) = visible {
+            self.state.style_context.visibility.push(visible);
+        }
+        // A hidden table's borders are drawn in blanks, keeping its shape.
+        let border = self.theme.table_border.map(|border| match self.state.style_context.hidden() {
+            true => TableBorder { symbols: BLANK_BORDER, ..border },
+            false => border,
+        });
+        let mut table = Table::new(border);
+
+        if let Some(caption) = caption.filter(|caption| !self.is_display_none(document, *caption)) {
+            table.set_caption(self.lay_out_subtree(document, caption, &[element_style.style]));
+        }
+        for TableRow { cells, styles: [group_style, row_style] } in head.into_iter().chain(body).chain(foot) {
+            table.add_row();
+            for cell in cells {
+                if self.is_display_none(document, cell) || !table.has_room() {
+                    continue;
                 }
+                let Some(element) = document.node(cell).element() else {
+                    continue;
+                };
+
+                let (rows, columns) = (element.rowspan(), element.colspan());
+                table.add_cell(self.lay_out_subtree(document, cell, &[element_style.style, group_style, row_style]), rows, columns);
+            }
+        }
+
+        let LayoutState { spans, style_context, pending_source, flow, .. } = &mut self.state;
+        let out = &mut self.out;
+
+        // Like the start of any other block.
+        flow.push_text(out, std::mem::take(spans), pending_source.take());
+
+        let block = element_style.block.clone().unwrap_or_default();
+        flow.open(out, adapt_block(style_context, &block), document.span(id));
+
+        // Drawn at a fallback width until the area it goes in is known.
+        let lines = table.lines(FALLBACK_WIDTH);
+        let count = lines.len();
+        flow.push(out, lines, Vec::new(), Some(document.span(id)));
+        if count > 0 {
+            out.tables.push(TableLines { line: out.lines.len() - count, lines: count, table });
+        }
+        flow.close(out, document.span(id));
+
+        if visible.is_some() {
+            style_context.visibility.pop();
+        }
+    }
+
+    // Sorts `child`, of a row group or of the table itself, into `rows`: a
+    // <tr> is a row, and cells outside of one make a row with the cells next
+    // to them. Whatever is not part of the table is `fostered`.
+    fn sort_into_rows(&self, document: &Document, child: NodeId, group_style: Style, rows: &mut Vec<TableRow>, open_row: &mut bool, fostered: &mut Vec<NodeId>) {
+        let Some(element) = document.node(child).element() else {
+            if is_visible_text(document, child) {
+                fostered.push(child);
             }
+            return;
+        };
+
+        match element.tag {
+            HtmlTag::TR => {
+                *open_row = false;
+                let (style, inline) = resolve_style(&self.theme, &self.state.stylesheet, document, child, element);
+                if inline.display_none {
+                    return;
+                }
+
+                let mut cells = Vec::new();
+                for cell in document.children(child) {
+                    match document.node(cell).tag() {
+                        Some(HtmlTag::TD | HtmlTag::TH) => cells.push(cell),
+                        Some(_) => fostered.push(cell),
+                        None if is_visible_text(document, cell) => fostered.push(cell),
+                        None => {},
+                    }
+                }
+                rows.push(TableRow { cells, styles: [group_style, style.style] });
+            },
+            HtmlTag::TD | HtmlTag::TH => {
+                match (*open_row, rows.last_mut()) {
+                    (true, Some(row)) => row.cells.push(child),
+                    _ => rows.push(TableRow { cells: vec![child], styles: [group_style, Style::default()] }),
+                }
+                *open_row = true;
+            },
+            _ => fostered.push(child),
         }
     }
 
-    HtmlWidget {
-        paragraph: Paragraph::new(lines).wrap(Wrap { trim: false }),
-        links: style_context.link_list,
-        images: style_context.img_list
+    fn is_display_none(&self, document: &Document, id: NodeId) -> bool {
+        document.node(id).element().is_some_and(|element| resolve_style(&self.theme, &self.state.stylesheet, document, id, element).1.display_none)
+    }
+
+    // Lays out the subtree under `id` on its own, inside elements with
+    // `styles`. Links and images in it are numbered along with the rest of
+    // the document.
+    fn lay_out_subtree(&mut self, document: &Document, id: NodeId, styles: &[Style]) -> LaidOut<'a> {
+        let outer_out = std::mem::take(&mut self.out);
+        let mut outer_state = std::mem::take(&mut self.state);
+        self.state.style_context = std::mem::take(&mut outer_state.style_context);
+        self.state.stylesheet = std::mem::take(&mut outer_state.stylesheet);
+
+        for style in styles {
+            self.state.style_context.push_style(*style);
+        }
+        for edge in document.traverse(id) {
+            self.visit(document, edge);
+        }
+        self.flush();
+        for style in styles.iter().rev() {
+            self.state.style_context.pop_style(*style);
+        }
+
+        outer_state.style_context = std::mem::take(&mut self.state.style_context);
+        outer_state.stylesheet = std::mem::take(&mut self.state.stylesheet);
+        self.state = outer_state;
+        std::mem::replace(&mut self.out, outer_out)
+    }
+
+    pub(crate) fn truncate(&mut self, lines: usize) {
+        self.out.truncate(lines);
+    }
+
+    // Whether the walk can be paused here. It can't inside a table, which
+    // is laid out all at once when the walk gets to it, or after a quote's
+    // attribution while the quote could still go on.
+    pub(crate) fn can_checkpoint(&self) -> bool {
+        self.state.skipping.is_none() && self.state.attributed.is_none()
+    }
+
+    // Makes a line of whatever is left over once the walk is done, and
+    // draws the margin after the last block.
+    pub(crate) fn flush(&mut self) {
+        let state = &mut self.state;
+
+        if !state.spans.is_empty() {
+            state.flow.push(&mut self.out, vec![Line::from(std::mem::take(&mut state.spans))], Vec::new(), state.pending_source.take());
+        }
+        state.flow.finish(&mut self.out);
+    }
+
+    pub(crate) fn lines(&self) -> &[Line<'a>] {
+        &self.out.lines
+    }
+
+    pub(crate) fn line_sources(&self) -> &[Option<SourceSpan>] {
+        &self.out.sources
+    }
+
+    pub(crate) fn line_layouts(&self) -> &[LineLayout] {
+        &self.out.layouts
+    }
+
+    pub(crate) fn rules(&self) -> &[RuleLine] {
+        &self.out.rules
     }
+
+    pub(crate) fn tables(&self) -> &[TableLines<'a>] {
+        &self.out.tables
+    }
+
+    pub(crate) fn links(&self) -> &[String] {
+        &self.state.style_context.link_list
+    }
+
+    pub(crate) fn images(&self) -> &[String] {
+        &self.state.style_context.img_list
+    }
+
+    pub(crate) fn into_widget(mut self, document: Document) -> HtmlWidget<'a> {
+        self.flush();
+
+        HtmlWidget {
+            paragraph: Paragraph::new(self.out.lines.clone()).wrap(Wrap { trim: false }),
+            lines: self.out.lines,
+            line_layouts: self.out.layouts,
+            rules: self.out.rules,
+            tables: self.out.tables,
+            links: self.state.style_context.link_list,
+            images: self.state.style_context.img_list,
+            document,
+            line_sources: self.out.sources,
+            fitted: Default::default(),
+        }
+    }
+}
+
+// The theme's style for `element` with the style sheet and its style
+// attribute laid over it. A <cite> at the end of a quote is styled as its
+// attribution.
+fn resolve_style(theme: &Theme, stylesheet: &StyleSheet, document: &Document, id: NodeId, element: &HtmlElement) -> (ElementStyle, InlineStyle) {
+    let mut element_style = theme.resolve(element);
+    let inline = stylesheet.compute(document, id);
+
+    if let Some(attribution) = theme.attribution.as_ref().filter(|_| is_attribution(document, id)) {
+        element_style = ElementStyle { style: attribution.style.patch(element_style.style), ..attribution.clone() };
+    }
+
+    element_style.style = element_style.style.patch(inline.style);
+    (element_style, inline)
 }
 
+// Whether `id` is a <cite> that nothing but whitespace and comments come
+// after in a quote.
+fn is_attribution(document: &Document, id: NodeId) -> bool {
+    if document.node(id).tag() != Some(&HtmlTag::CITE) {
+        return false;
+    }
+    let Some(quote) = document.parent(id).filter(|quote| document.node(*quote).tag() == Some(&HtmlTag::BLOCKQUOTE)) else {
+        return false;
+    };
+
+    let mut last = document.last_child(quote);
+    while let Some(node) = last.filter(|node| *node != id) {
+        match &document.node(node).data {
+            NodeData::Text(text) if text.trim().is_empty() => {},
+            NodeData::Comment(_) => {},
+            _ => return false,
+        }
+        last = document.prev_sibling(node);
+    }
+    true
+}
+
+// `block` with its colors fitted to the terminal, and its gutter blank if
+// it is hidden.
+fn adapt_block(context: &StyleContext, block: &BlockStyle) -> BlockStyle {
+    BlockStyle {
+        line_style: context.palette.adapt(block.line_style),
+        gutter: block.gutter.clone().map(|gutter| {
+            let style = context.palette.adapt(gutter.style);
+            context.hide(gutter.style(style))
+        }),
+        ..block.clone()
+    }
+}
+
+// Text that is more than the whitespace between elements.
+fn is_visible_text(document: &Document, id: NodeId) -> bool {
+    matches!(&document.node(id).data, NodeData::Text(text) if !text.trim().is_empty())
+}
+
+fn merge_source(pending: Option<SourceSpan>, span: SourceSpan) -> SourceSpan {
+    match pending {
+        Some(pending) => pending.union(&span),
+        None => span,
+    }
+}
+
+#[cfg(test)]
 mod test {
     #![allow(dead_code)]
-    use std::collections::HashMap;
+    use std::io::{self, Read};
 
     use insta::assert_snapshot;
-    use ratatui::{Terminal, backend::TestBackend};
+    use ratatui::{Terminal, backend::TestBackend, buffer::Buffer, layout::{Alignment, Rect}, widgets::Widget, style::{Color, Style, Stylize}, text::{Line, Span}, widgets::{Paragraph, Wrap}};
 
-    use crate::tuihtml::{html::{HtmlElement, HtmlTag}, parser::{construct_widget, parse_html}, tokenizer::Token, widget::HtmlWidget};
+    use crate::tuihtml::{color::{Background, ColorDepth, Palette}, diagnostics::{ParseError, ParseErrorKind}, dom::Document, error::Error, html::{Attributes, HtmlElement, HtmlTag}, parser::{construct_widget, construct_widget_with_diagnostics, parse_document, parse_document_from_reader, try_parse_document, try_parse_document_from_bytes, try_parse_document_from_reader}, options::{Limit, ParseOptions}, theme::{BlockStyle, ElementStyle, Theme}, tokenizer::{tokenize, HtmlTokenizer, Token}, widget::HtmlWidget};
 
+    fn parse_html(html: &str) -> Vec<Token> {
+        collect_tokens(html, false).0
+    }
+
+    // Like parse_html(), also returning the parse errors the tokenizer
+    // recovered from.
+    fn parse_html_with_diagnostics(html: &str) -> (Vec<Token>, Vec<ParseError>) {
+        collect_tokens(html, true)
+    }
+
+    // The token list keeps its trailing Token::Eof, which the tokenizer as an
+    // iterator leaves out.
+    fn collect_tokens(html: &str, diagnostics: bool) -> (Vec<Token>, Vec<ParseError>) {
+        let mut tokenizer = tokenize(html);
+        if diagnostics {
+            tokenizer.enable_diagnostics();
+        }
+
+        let mut tokens: Vec<Token> = tokenizer.by_ref().collect();
+        tokens.push(Token::Eof);
+
+        (tokens, tokenizer.take_errors())
+    }
 
     #[test]
     fn parse_html_returns_only_text() {
@@ -329,7 +992,7 @@ Title
 Hello World
 Google"#;
 
-        let tokens = parse_html(html.into());
+        let tokens = parse_html(html);
 
         assert_eq!(tokens, Vec::from([
             Token::Text("Title".into()),
@@ -356,27 +1019,27 @@ Google"#;
     "#;
 
 
-        let tokens = parse_html(html.into());
+        let tokens = parse_html(html);
 
         assert_eq!(tokens, Vec::from([
-            Token::Element(HtmlElement { tag: HtmlTag::HTML, attributes: HashMap::new(), closing: false }),
-            Token::Element(HtmlElement { tag: HtmlTag::BODY, attributes: HashMap::new(), closing: false }),
-            Token::Element(HtmlElement { tag: HtmlTag::H1, attributes: HashMap::new(), closing: false }),
+            Token::Element(HtmlElement { tag: HtmlTag::HTML, attributes: Attributes::new(), closing: false, self_closing: false }),
+            Token::Element(HtmlElement { tag: HtmlTag::BODY, attributes: Attributes::new(), closing: false, self_closing: false }),
+            Token::Element(HtmlElement { tag: HtmlTag::H1, attributes: Attributes::new(), closing: false, self_closing: false }),
             Token::Text("Title".into()),
-            Token::Element(HtmlElement { tag: HtmlTag::H1, attributes: HashMap::new(), closing: true }),
-            Token::Element(HtmlElement { tag: HtmlTag::P, attributes: HashMap::new(), closing: false }),
-            Token::Element(HtmlElement { tag: HtmlTag::BOLD, attributes: HashMap::new(), closing: false }),
+            Token::Element(HtmlElement { tag: HtmlTag::H1, attributes: Attributes::new(), closing: true, self_closing: false }),
+            Token::Element(HtmlElement { tag: HtmlTag::P, attributes: Attributes::new(), closing: false, self_closing: false }),
+            Token::Element(HtmlElement { tag: HtmlTag::BOLD, attributes: Attributes::new(), closing: false, self_closing: false }),
             Token::Text("Hello".into()),
-            Token::Element(HtmlElement { tag: HtmlTag::BOLD, attributes: HashMap::new(), closing: true }),
+            Token::Element(HtmlElement { tag: HtmlTag::BOLD, attributes: Attributes::new(), closing: true, self_closing: false }),
             Token::Text(" World".into()),
-            Token::Element(HtmlElement { tag: HtmlTag::P, attributes: HashMap::new(), closing: true }),
-            Token::Element(HtmlElement { tag: HtmlTag::A, attributes: HashMap::from([("href".into(), "https://www.google.com".into()); 1]), closing: false}),
+            Token::Element(HtmlElement { tag: HtmlTag::P, attributes: Attributes::new(), closing: true, self_closing: false }),
+            Token::Element(HtmlElement { tag: HtmlTag::A, attributes: Attributes::from([("href".into(), "https://www.google.com".into()); 1]), closing: false, self_closing: false }),
             Token::Text("Google".into()),
-            Token::Element(HtmlElement { tag: HtmlTag::A, attributes: HashMap::new(), closing: true }),
-            Token::Element(HtmlElement { tag: HtmlTag::IMG, attributes: HashMap::from([("src".into(), "/home/garrett/Documents/image.jpg".into()); 1]), closing: false }),
-            Token::Element(HtmlElement { tag: HtmlTag::IMG, attributes: HashMap::new(), closing: true }),
-            Token::Element(HtmlElement { tag: HtmlTag::BODY, attributes: HashMap::new(), closing: true }),
-            Token::Element(HtmlElement { tag: HtmlTag::HTML, attributes: HashMap::new(), closing: true }),
+            Token::Element(HtmlElement { tag: HtmlTag::A, attributes: Attributes::new(), closing: true, self_closing: false }),
+            Token::Element(HtmlElement { tag: HtmlTag::IMG, attributes: Attributes::from([("src".into(), "/home/garrett/Documents/image.jpg".into()); 1]), closing: false, self_closing: false }),
+            Token::Element(HtmlElement { tag: HtmlTag::IMG, attributes: Attributes::new(), closing: true, self_closing: false }),
+            Token::Element(HtmlElement { tag: HtmlTag::BODY, attributes: Attributes::new(), closing: true, self_closing: false }),
+            Token::Element(HtmlElement { tag: HtmlTag::HTML, attributes: Attributes::new(), closing: true, self_closing: false }),
             Token::Eof
         ]))
     }
@@ -392,27 +1055,683 @@ Google"#;
     "#;
 
 
-        let tokens = parse_html(html.into());
+        let tokens = parse_html(html);
 
         assert_eq!(tokens, Vec::from([
-            Token::Element(HtmlElement { tag: HtmlTag::H1, attributes: HashMap::new(), closing: false }),
+            Token::Element(HtmlElement { tag: HtmlTag::H1, attributes: Attributes::new(), closing: false, self_closing: false }),
             Token::Text("Title".into()),
-            Token::Element(HtmlElement { tag: HtmlTag::H1, attributes: HashMap::new(), closing: true }),
-            Token::Element(HtmlElement { tag: HtmlTag::P, attributes: HashMap::new(), closing: false }),
-            Token::Element(HtmlElement { tag: HtmlTag::BOLD, attributes: HashMap::new(), closing: false }),
+            Token::Element(HtmlElement { tag: HtmlTag::H1, attributes: Attributes::new(), closing: true, self_closing: false }),
+            Token::Element(HtmlElement { tag: HtmlTag::P, attributes: Attributes::new(), closing: false, self_closing: false }),
+            Token::Element(HtmlElement { tag: HtmlTag::BOLD, attributes: Attributes::new(), closing: false, self_closing: false }),
             Token::Text("Hello".into()),
-            Token::Element(HtmlElement { tag: HtmlTag::BOLD, attributes: HashMap::new(), closing: true }),
+            Token::Element(HtmlElement { tag: HtmlTag::BOLD, attributes: Attributes::new(), closing: true, self_closing: false }),
             Token::Text(" World".into()),
-            Token::Element(HtmlElement { tag: HtmlTag::P, attributes: HashMap::new(), closing: true }),
-            Token::Element(HtmlElement { tag: HtmlTag::A, attributes: HashMap::from([("href".into(), "https://www.google.com".into()); 1]), closing: false}),
+            Token::Element(HtmlElement { tag: HtmlTag::P, attributes: Attributes::new(), closing: true, self_closing: false }),
+            Token::Element(HtmlElement { tag: HtmlTag::A, attributes: Attributes::from([("href".into(), "https://www.google.com".into()); 1]), closing: false, self_closing: false }),
             Token::Text("Google".into()),
-            Token::Element(HtmlElement { tag: HtmlTag::A, attributes: HashMap::new(), closing: true }),
-            Token::Element(HtmlElement { tag: HtmlTag::IMG, attributes: HashMap::from([("src".into(), "/home/garrett/Documents/image.jpg".into()); 1]), closing: false }),
-            Token::Element(HtmlElement { tag: HtmlTag::IMG, attributes: HashMap::new(), closing: true }),
+            Token::Element(HtmlElement { tag: HtmlTag::A, attributes: Attributes::new(), closing: true, self_closing: false }),
+            Token::Element(HtmlElement { tag: HtmlTag::IMG, attributes: Attributes::from([("src".into(), "/home/garrett/Documents/image.jpg".into()); 1]), closing: false, self_closing: false }),
+            Token::Element(HtmlElement { tag: HtmlTag::IMG, attributes: Attributes::new(), closing: true, self_closing: false }),
             Token::Eof
         ]))
     }
 
+    #[test]
+    fn parse_html_decodes_character_references() {
+
+        let html = r#"<a href="/feed?id=1&amp;sort=new&copy=2">Tom&apos;s &mdash; &#8216;RSS&#x2019;</a>"#;
+
+        let tokens = parse_html(html);
+
+        assert_eq!(tokens, Vec::from([
+            Token::Element(HtmlElement { tag: HtmlTag::A, attributes: Attributes::from([("href".into(), "/feed?id=1&sort=new&copy=2".into()); 1]), closing: false, self_closing: false }),
+            Token::Text("Tom's \u{2014} \u{2018}RSS\u{2019}".into()),
+            Token::Element(HtmlElement { tag: HtmlTag::A, attributes: Attributes::new(), closing: true, self_closing: false }),
+            Token::Eof
+        ]))
+    }
+
+    #[test]
+    fn parse_html_returns_self_closing_elements() {
+
+        let html = r#"<p>One<br/>Two<br />Three<img src="/a.png"/></p>"#;
+
+        let tokens = parse_html(html);
+
+        assert_eq!(tokens, Vec::from([
+            Token::Element(HtmlElement { tag: HtmlTag::P, attributes: Attributes::new(), closing: false, self_closing: false }),
+            Token::Text("One".into()),
+            Token::Element(HtmlElement { tag: HtmlTag::BR, attributes: Attributes::new(), closing: false, self_closing: true }),
+            Token::Text("Two".into()),
+            Token::Element(HtmlElement { tag: HtmlTag::BR, attributes: Attributes::new(), closing: false, self_closing: true }),
+            Token::Text("Three".into()),
+            Token::Element(HtmlElement { tag: HtmlTag::IMG, attributes: Attributes::from([("src".into(), "/a.png".into()); 1]), closing: false, self_closing: true }),
+            Token::Element(HtmlElement { tag: HtmlTag::P, attributes: Attributes::new(), closing: true, self_closing: false }),
+            Token::Eof
+        ]))
+    }
+
+    #[test]
+    fn void_elements_do_not_disturb_element_stack() {
+        let html = r#"<p><b>Bold<img src="/a.png"/></b> plain<br/><input></input>text</p>"#;
+
+        let widget = construct_widget(html);
+
+        assert_eq!(widget.images, vec![String::from("/a.png")]);
+        assert_eq!(widget.paragraph, Paragraph::new(vec![
+            Line::from(vec![Span::from("Bold").bold(), Span::from(" plain")]),
+            Line::from(vec![Span::from("text")]),
+            Line::raw("\u{00A0}"),
+        ]).wrap(Wrap { trim: false }));
+    }
+
+    #[test]
+    fn parse_document_builds_node_tree() {
+        let html = r#"<div id="post"><h1>Title</h1><p><b>Hello</b> <a href="https://ratatui.rs">World</a><br></p></div>"#;
+
+        let document = parse_document(html);
+
+        let div = document.find(HtmlTag::DIV).unwrap();
+        assert_eq!(document.parent(div), Some(document.root()));
+        assert_eq!(document.attr(div, "id"), Some("post"));
+
+        let children: Vec<Option<&HtmlTag>> = document.children(div).map(|id| document.node(id).tag()).collect();
+        assert_eq!(children, vec![Some(&HtmlTag::H1), Some(&HtmlTag::P)]);
+
+        let p = document.find(HtmlTag::P).unwrap();
+        let h1 = document.prev_sibling(p).unwrap();
+        assert_eq!(document.text_content(h1), "Title");
+        assert_eq!(document.text_content(p), "Hello World");
+        assert_eq!(document.node(document.last_child(p).unwrap()).tag(), Some(&HtmlTag::BR));
+
+        let a = document.find(HtmlTag::A).unwrap();
+        assert_eq!(document.node(a).attr("href"), Some("https://ratatui.rs"));
+        assert_eq!(document.ancestors(a).collect::<Vec<_>>(), vec![p, div, document.root()]);
+    }
+
+    #[test]
+    fn misnested_markup_does_not_leak_styles() {
+        let html = r#"<p><b>one<i>two</b>three</i></b> four<p>five"#;
+
+        let widget = construct_widget(html);
+
+        assert_eq!(widget.paragraph, Paragraph::new(vec![
+            Line::from(vec![
+                Span::from("one").bold(),
+                Span::from("two").bold().italic(),
+                Span::from("three").italic(),
+                Span::from(" four"),
+            ]),
+            Line::raw("\u{00A0}"),
+            Line::from(vec![Span::from("five")]),
+            Line::raw("\u{00A0}"),
+        ]).wrap(Wrap { trim: false }));
+    }
+
+    #[test]
+    fn parse_html_returns_markup_declarations() {
+
+        let html = r#"<!DOCTYPE html>
+<?xml version="1.0"?><!-- a > b --><p>x < y<![CDATA[<raw>]]></p><!-->"#;
+
+        let tokens = parse_html(html);
+
+        assert_eq!(tokens, Vec::from([
+            Token::Doctype("html".into()),
+            Token::Comment("?xml version=\"1.0\"?".into()),
+            Token::Comment(" a > b ".into()),
+            Token::Element(HtmlElement { tag: HtmlTag::P, attributes: Attributes::new(), closing: false, self_closing: false }),
+            Token::Text("x < y".into()),
+            Token::CData("<raw>".into()),
+            Token::Element(HtmlElement { tag: HtmlTag::P, attributes: Attributes::new(), closing: true, self_closing: false }),
+            Token::Comment("".into()),
+            Token::Eof
+        ]))
+    }
+
+    #[test]
+    fn comments_and_doctype_are_not_rendered() {
+        let html = r#"<!doctype html><!-- <p>hidden</p> --><p>shown</p>"#;
+
+        let widget = construct_widget(html);
+
+        assert_eq!(widget.document.doctype(), Some("html"));
+        assert_eq!(widget.paragraph, Paragraph::new(vec![
+            Line::from(vec![Span::from("shown")]),
+            Line::raw("\u{00A0}"),
+        ]).wrap(Wrap { trim: false }));
+    }
+
+    #[test]
+    fn parse_html_returns_raw_text_as_single_token() {
+
+        let html = "<script>if (a < b && c) {\n  document.write('<p>x</p>');\n}</SCRIPT ><textarea>&lt;b&gt; </textareas></textarea>";
+
+        let tokens = parse_html(html);
+
+        assert_eq!(tokens, Vec::from([
+            Token::Element(HtmlElement { tag: HtmlTag::SCRIPT, attributes: Attributes::new(), closing: false, self_closing: false }),
+            Token::Text("if (a < b && c) {\n  document.write('<p>x</p>');\n}".into()),
+            Token::Element(HtmlElement { tag: HtmlTag::SCRIPT, attributes: Attributes::new(), closing: true, self_closing: false }),
+            Token::Element(HtmlElement { tag: HtmlTag::TEXTAREA, attributes: Attributes::new(), closing: false, self_closing: false }),
+            Token::Text("<b> </textareas>".into()),
+            Token::Element(HtmlElement { tag: HtmlTag::TEXTAREA, attributes: Attributes::new(), closing: true, self_closing: false }),
+            Token::Eof
+        ]))
+    }
+
+    #[test]
+    fn script_style_and_title_are_hidden() {
+        let html = r#"<title>Page</title><style>p { color: red; }</style><p>shown<script>alert("<b>hi</b>")</script></p>"#;
+
+        let widget = construct_widget(html);
+
+        assert_eq!(widget.paragraph, Paragraph::new(vec![
+            // The style sheet is applied rather than shown.
+            Line::from(vec![Span::from("shown").light_red()]),
+            Line::raw("\u{00A0}"),
+        ]).wrap(Wrap { trim: false }));
+    }
+
+    #[test]
+    fn iframe_fallback_is_hidden() {
+        let html = "<p>before<iframe src=/embed><p>Your browser does not support iframes</p></iframe>after</p>";
+
+        let widget = construct_widget(html);
+
+        assert_eq!(widget.lines, vec![
+            Line::from(vec![Span::from("before"), Span::from("after")]),
+            Line::raw("\u{00A0}"),
+        ]);
+    }
+
+    #[test]
+    fn parse_html_returns_unquoted_and_boolean_attributes() {
+
+        let html = r#"<input disabled><td colspan=2 COLSPAN=3 Data-X = 'say "hi"' title="it's"/><details open/><a href=/feed/>x</a>"#;
+
+        let tokens = parse_html(html);
+
+        assert_eq!(tokens, Vec::from([
+            Token::Element(HtmlElement { tag: HtmlTag::INPUT, attributes: Attributes::from([("disabled".into(), "".into())]), closing: false, self_closing: false }),
+            Token::Element(HtmlElement { tag: HtmlTag::TD, attributes: Attributes::from([
+                ("colspan".into(), "2".into()),
+                ("data-x".into(), "say \"hi\"".into()),
+                ("title".into(), "it's".into()),
+            ]), closing: false, self_closing: true }),
+            Token::Element(HtmlElement { tag: HtmlTag::DETAILS, attributes: Attributes::from([("open".into(), "".into())]), closing: false, self_closing: true }),
+            Token::Element(HtmlElement { tag: HtmlTag::A, attributes: Attributes::from([("href".into(), "/feed/".into())]), closing: false, self_closing: false }),
+            Token::Text("x".into()),
+            Token::Element(HtmlElement { tag: HtmlTag::A, attributes: Attributes::new(), closing: true, self_closing: false }),
+            Token::Eof
+        ]))
+    }
+
+    #[test]
+    fn ordered_lists_respect_start_and_value() {
+        let html = r#"<ol start="3"><li>three<li value=10>ten<li>eleven</ol>"#;
+
+        let widget = construct_widget(html);
+
+        assert_eq!(widget.paragraph, Paragraph::new(vec![
+            Line::from(vec![Span::raw("  "), Span::from("3. "), Span::from("three")]),
+            Line::from(vec![Span::raw("  "), Span::from("10. "), Span::from("ten")]),
+            Line::from(vec![Span::raw("  "), Span::from("11. "), Span::from("eleven")]),
+            Line::raw("\u{00A0}"),
+        ]).wrap(Wrap { trim: false }));
+    }
+
+    #[test]
+    fn list_numbers_stop_at_the_ends_of_their_range() {
+        let html = r#"<ol start="9223372036854775807"><li>a<li>b</ol><ol start="-9223372036854775808"><li>c</ol>"#;
+
+        let widget = construct_widget(html);
+
+        assert_eq!(widget.paragraph, Paragraph::new(vec![
+            Line::from(vec![Span::raw("  "), Span::from("9223372036854775807. "), Span::from("a")]),
+            Line::from(vec![Span::raw("  "), Span::from("9223372036854775807. "), Span::from("b")]),
+            Line::raw("\u{00A0}"),
+            // The number before the first one is kept, which can't go lower.
+            Line::from(vec![Span::raw("  "), Span::from("-9223372036854775807. "), Span::from("c")]),
+            Line::raw("\u{00A0}"),
+        ]).wrap(Wrap { trim: false }));
+    }
+
+    #[test]
+    fn tokens_nodes_and_lines_carry_source_spans() {
+        let html = "<p>caf\u{e9} <b>one</b>\n  two</p>\n<p>three";
+
+        let mut tokenizer = HtmlTokenizer::new(html.chars());
+        let spans: Vec<(usize, usize, usize, usize)> = std::iter::from_fn(|| match tokenizer.next_with_span() {
+            (Token::Eof, _) => None,
+            (_, span) => Some((span.start.offset, span.end.offset, span.start.line, span.start.column)),
+        }).collect();
+        // The whitespace after "</b>" and "</p>" is swallowed with the tag.
+        assert_eq!(spans, vec![
+            (0, 3, 1, 1),
+            (3, 9, 1, 4),
+            (9, 12, 1, 9),
+            (12, 15, 1, 12),
+            (15, 19, 1, 15),
+            (22, 25, 2, 3),
+            (25, 29, 2, 6),
+            (30, 33, 3, 1),
+            (33, 38, 3, 4),
+        ]);
+
+        let widget = construct_widget(html);
+        let document = widget.get_document();
+
+        let b = document.find(HtmlTag::BOLD).unwrap();
+        assert_eq!(document.span(b).slice(html), Some("<b>one</b>"));
+        let p = document.find(HtmlTag::P).unwrap();
+        assert_eq!(document.span(p).slice(html), Some("<p>caf\u{e9} <b>one</b>\n  two</p>"));
+        let last_p = document.find_all(HtmlTag::P).last().unwrap();
+        assert_eq!(document.span(last_p).slice(html), Some("<p>three"));
+
+        assert_eq!(widget.get_line_source(0).and_then(|span| span.slice(html)), Some("<p>caf\u{e9} <b>one</b>\n  two</p>"));
+        assert_eq!(widget.get_line_source(2).and_then(|span| span.slice(html)), Some("<p>three"));
+        assert_eq!(widget.get_line_source(4), None);
+    }
+
+    #[test]
+    fn diagnostics_report_malformed_markup_in_source_order() {
+        let html = "<div><p>one</b>\n<span>two</div>\n<a href=\"x\" href=y>three";
+        let (widget, errors) = construct_widget_with_diagnostics(html);
+
+        let kinds: Vec<_> = errors.iter().map(|error| error.kind).collect();
+        assert_eq!(kinds, vec![
+            ParseErrorKind::StrayEndTag,
+            ParseErrorKind::MisnestedTag,
+            ParseErrorKind::DuplicateAttribute,
+            ParseErrorKind::UnclosedElement,
+        ]);
+        assert_eq!(errors[0].to_string(), "1:12: unexpected end tag </b>");
+        assert_eq!(errors[0].span.slice(html), Some("</b>"));
+        assert_eq!(errors[1].to_string(), "2:10: end tag </div> closes unclosed <span>");
+        assert_eq!(errors[2].span.slice(html), Some("<a href=\"x\" href=y"));
+        assert_eq!(errors[3].to_string(), "3:1: <a> is never closed");
+
+        // Diagnostics don't change the output.
+        assert_eq!(widget.get_document(), construct_widget(html).get_document());
+    }
+
+    #[test]
+    fn diagnostics_report_unexpected_end_of_input() {
+        let (_, errors) = parse_html_with_diagnostics("<p title=\"unclosed>text</p>");
+        let kinds: Vec<_> = errors.iter().map(|error| error.kind).collect();
+        assert_eq!(kinds, vec![ParseErrorKind::UnclosedQuote, ParseErrorKind::EofInTag]);
+
+        let (_, errors) = parse_html_with_diagnostics("text<!-- never closed");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ParseErrorKind::EofInComment);
+        assert_eq!(errors[0].span.start.column, 5);
+
+        let (_, errors) = parse_html_with_diagnostics("<p>fine</p>");
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn tokenizer_is_an_iterator_that_ends_at_eof() {
+        let html = "<p>one <b>two</b></p>\n<p>three</p>";
+
+        let text: Vec<String> = tokenize(html).filter_map(|token| Token::get_text(&token)).collect();
+        assert_eq!(text, vec!["one ", "two", "three"]);
+
+        let mut tokenizer = tokenize(html).peekable();
+        assert!(tokenizer.peek().is_some_and(|token| Token::get_html_element(token).is_some()));
+        assert_eq!(tokenizer.by_ref().take_while(|token| *token != Token::Text("two".into())).count(), 3);
+        assert_eq!(tokenizer.count(), 5);
+
+        let mut tokenizer = tokenize("");
+        assert_eq!(tokenizer.next(), None);
+        assert_eq!(tokenizer.next(), None);
+
+        // parse_html still ends its tokens with Token::Eof.
+        assert_eq!(parse_html(html).last(), Some(&Token::Eof));
+        assert_eq!(parse_html(html).len(), tokenize(html).count() + 1);
+    }
+
+    // Hands out one byte per read, splitting multi-byte characters, then
+    // fails if `error` is set.
+    struct Trickle<'a> {
+        bytes: &'a [u8],
+        error: bool,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.bytes.split_first() {
+                Some((byte, rest)) => {
+                    buf[0] = *byte;
+                    self.bytes = rest;
+                    Ok(1)
+                },
+                None if self.error => Err(io::Error::other("connection reset")),
+                None => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn reader_input_tokenizes_like_string_input() {
+        let html = "<p title=\"caf\u{e9}\">na\u{ef}ve \u{1F600}</p>\n<script>a<b</script>x < y";
+
+        let mut from_str = HtmlTokenizer::new(html.chars());
+        let mut from_reader = HtmlTokenizer::from_reader(Trickle { bytes: html.as_bytes(), error: false });
+
+        loop {
+            let expected = from_str.next_with_span();
+            assert_eq!(from_reader.next_with_span(), expected);
+            if expected.0 == Token::Eof {
+                break;
+            }
+        }
+        assert!(from_reader.take_io_error().is_none());
+
+        // Invalid bytes become U+FFFD instead of ending the input.
+        let mut tokenizer = HtmlTokenizer::from_reader(&b"a\xffb"[..]);
+        assert_eq!(tokenizer.next(), Some(Token::Text("a\u{fffd}b".into())));
+    }
+
+    #[test]
+    fn reader_errors_are_returned() {
+        let document = parse_document_from_reader(Trickle { bytes: b"<p>one</p>", error: false }).unwrap();
+        assert_eq!(document, parse_document("<p>one</p>"));
+
+        let error = parse_document_from_reader(Trickle { bytes: b"<p>one", error: true }).unwrap_err();
+        assert_eq!(error.to_string(), "connection reset");
+    }
+
+    #[test]
+    fn try_parse_fails_past_the_limits() {
+        let options = ParseOptions { max_depth: 3, max_document_size: 64, ..ParseOptions::default() };
+
+        let document = try_parse_document("<div><p><b>ok</b></p></div>", &options).unwrap();
+        assert_eq!(document, parse_document("<div><p><b>ok</b></p></div>"));
+
+        let error = try_parse_document("<div>\n<p><b><i>deep</i></b></p></div>", &options).unwrap_err();
+        assert!(matches!(error, Error::NestingTooDeep { limit: 3, span } if span.start.line == 2 && span.start.column == 7));
+        assert_eq!(error.to_string(), "2:7: elements nested more than 3 deep");
+
+        let long = "<p>".repeat(30);
+        assert!(matches!(try_parse_document(&long, &options), Err(Error::DocumentTooLarge { size: 90, limit: 64 })));
+        assert!(matches!(try_parse_document_from_reader(long.as_bytes(), &options), Err(Error::DocumentTooLarge { size: 65.., limit: 64 })));
+        assert!(try_parse_document_from_reader(&long.as_bytes()[..60], &options).is_ok());
+    }
+
+    #[test]
+    fn try_build_fails_past_every_limit() {
+        let options = ParseOptions { max_tokens: 4, ..ParseOptions::default() };
+        let error = HtmlWidget::try_build("<p>one</p><p>two</p>", None, Some(&options)).unwrap_err();
+        assert!(matches!(error, Error::TooManyTokens { limit: 4 }));
+        assert_eq!(error.to_string(), "document has more than 4 tokens");
+
+        let options = ParseOptions { max_attributes: 1, ..ParseOptions::default() };
+        let error = HtmlWidget::try_build("<p a=1 b=2>one</p>", None, Some(&options)).unwrap_err();
+        assert!(matches!(error, Error::TooManyAttributes { limit: 1 }));
+
+        let options = ParseOptions { max_lines: 1, ..ParseOptions::default() };
+        let error = HtmlWidget::try_build("<p>one</p><p>two</p>", None, Some(&options)).unwrap_err();
+        assert!(matches!(error, Error::TooManyLines { limit: 1 }));
+        let error = HtmlWidget::try_from_bytes(b"<p>one</p><p>two</p>", None, None, Some(&options)).unwrap_err();
+        assert!(matches!(error, Error::TooManyLines { limit: 1 }));
+        let error = HtmlWidget::try_from_reader(&b"<p>one</p><p>two</p>"[..], None, Some(&options)).unwrap_err();
+        assert!(matches!(error, Error::TooManyLines { limit: 1 }));
+
+        let theme = Theme { link_marker: None, ..Theme::default() };
+        let options = ParseOptions { max_lines: 2, ..ParseOptions::default() };
+        let widget = HtmlWidget::try_from_bytes(b"<p><a href=/>x</a></p>", None, Some(&theme), Some(&options)).unwrap();
+        assert_eq!(widget.get_paragraph(), HtmlWidget::build("<p><a href=/>x</a></p>", Some(&theme), None).0.get_paragraph());
+    }
+
+    #[test]
+    fn limits_truncate_and_are_reported() {
+        let depth = |document: &Document| {
+            document.descendants(document.root()).map(|id| document.ancestors(id).count()).max().unwrap_or(0)
+        };
+
+        let options = ParseOptions { max_depth: 100, ..ParseOptions::default() };
+        let html = format!("{}deep", "<b>".repeat(100_000));
+        let (widget, limits) = HtmlWidget::build(&html, None, Some(&options));
+        assert_eq!(limits, vec![Limit::Depth]);
+        assert_eq!(depth(widget.get_document()), 101);
+        assert_eq!(widget.get_paragraph(), Paragraph::new(vec![Line::from("deep".bold())]).wrap(Wrap { trim: false }));
+
+        let options = ParseOptions { max_tokens: 4, max_attributes: 1, max_lines: 1, ..ParseOptions::default() };
+        let (widget, limits) = HtmlWidget::build("<p a=1 b=2>one</p><p>two</p>", None, Some(&options));
+        assert_eq!(limits, vec![Limit::Tokens, Limit::Attributes, Limit::Lines]);
+        let p = widget.get_document().find(HtmlTag::P).unwrap();
+        assert_eq!(widget.get_document().node(p).element().unwrap().attributes.len(), 1);
+        assert_eq!(widget.get_document().text_content(widget.get_document().root()), "one");
+        assert_eq!(widget.line_sources.len(), 1);
+
+        // The size limit falls inside the \u{e9}, which is cut along with it.
+        let options = ParseOptions { max_document_size: 7, ..ParseOptions::default() };
+        let (widget, limits) = HtmlWidget::build("<p>caf\u{e9} more</p>", None, Some(&options));
+        assert_eq!(limits, vec![Limit::DocumentSize]);
+        assert_eq!(widget.get_document().text_content(widget.get_document().root()), "caf");
+
+        let (_, limits) = HtmlWidget::build("<p>fine</p>", None, Some(&ParseOptions::default()));
+        assert!(limits.is_empty());
+
+        // Without options, the default limits apply and are reported too.
+        let (widget, limits) = HtmlWidget::build(&format!("{}deep", "<div>".repeat(1000)), None, None);
+        assert_eq!(limits, vec![Limit::Depth]);
+        assert_eq!(depth(widget.get_document()), ParseOptions::default().max_depth + 1);
+        let attributes: String = (0..300).map(|i| format!(" a{}", i)).collect();
+        let (_, limits) = HtmlWidget::build(&format!("<p{}>x</p>", attributes), None, None);
+        assert_eq!(limits, vec![Limit::Attributes]);
+    }
+
+    #[test]
+    fn try_parse_fails_on_bad_input() {
+        let options = ParseOptions::default();
+
+        let error = try_parse_document_from_bytes(b"<meta charset=utf-8><p>caf\xe9</p>", None, &options).unwrap_err();
+        assert!(matches!(error, Error::Decode { encoding: "UTF-8" }));
+        assert!(try_parse_document_from_bytes(b"<p>caf\xe9</p>", Some("latin1"), &options).is_ok());
+
+        let error = try_parse_document_from_reader(Trickle { bytes: b"<p>one", error: true }, &options).unwrap_err();
+        assert!(matches!(error, Error::Io(_)));
+        assert_eq!(error.to_string(), "failed to read HTML: connection reset");
+    }
+
+    #[test]
+    fn default_theme_draws_headings_and_rules() {
+        let html = "<h1>Title</h1><h2>Sub</h2><p>a<hr>b</p>";
+
+        let widget = construct_widget(html);
+
+        assert_eq!(widget.paragraph, Paragraph::new(vec![
+            Line::from(vec![Span::from("Title").bold()]).centered(),
+            Line::from("\u{00AF}".repeat(10)).centered(),
+            Line::raw("\u{00A0}"),
+            Line::from(vec![Span::from("Sub").bold()]).underlined(),
+            Line::from(vec![Span::from("a")]),
+            Line::raw("\u{00A0}"),
+            Line::from("_".repeat(120)).centered(),
+            Line::from("\u{00AF}".repeat(120)).centered(),
+            Line::from(vec![Span::from("b")]),
+            Line::raw("\u{00A0}"),
+        ]).wrap(Wrap { trim: false }));
+    }
+
+    #[test]
+    fn rules_fit_the_width_they_are_rendered_at() {
+        let widget = construct_widget("<h1>Headline</h1><hr><p>x</p>");
+
+        let render = || {
+            let mut buffer = Buffer::empty(Rect::new(0, 0, 12, 5));
+            (&widget).render(buffer.area, &mut buffer);
+            (0..5).map(|y| (0..12).map(|x| buffer[(x, y)].symbol()).collect()).collect::<Vec<String>>()
+        };
+
+        let rows = render();
+        assert_eq!(rows, vec![
+            "  Headline  ".to_owned(),
+            "\u{00AF}".repeat(12),
+            "_".repeat(12),
+            "\u{00AF}".repeat(12),
+            "x           ".to_owned(),
+        ]);
+        // The fitted paragraph is kept for the next frame at that width.
+        assert_eq!(widget.fitted.borrow().0, Some(12));
+        assert_eq!(render(), rows);
+        assert_eq!(widget.get_paragraph_for_width(40), Paragraph::new(vec![
+            Line::from(vec![Span::from("Headline").bold()]).centered(),
+            Line::from("\u{00AF}".repeat(16)).centered(),
+            Line::from("_".repeat(40)).centered(),
+            Line::from("\u{00AF}".repeat(40)).centered(),
+            Line::from(vec![Span::from("x")]),
+            Line::raw("\u{00A0}"),
+        ]).wrap(Wrap { trim: false }));
+        assert_eq!(widget.fitted.borrow().0, Some(40));
+        assert_eq!(render(), rows);
+    }
+
+    #[test]
+    fn themes_style_tags_classes_and_ids() {
+        let html = r#"<p class="note">Read <a href="/x">this</a> <b class="loud">now</b></p><div id="end">done</div>"#;
+
+        let mut theme = Theme::plain();
+        theme.set_tag(HtmlTag::P, ElementStyle {
+            block: Some(BlockStyle { margin_bottom: 2, ..BlockStyle::default() }),
+            ..ElementStyle::default()
+        });
+        theme.set_tag(HtmlTag::A, Style::new().blue().into());
+        theme.set_class("note", ElementStyle { prefix: Some(Span::from("> ")), ..Style::new().italic().into() });
+        theme.set_class("loud", ElementStyle { suffix: Some(Span::from("!").bold()), ..Style::new().red().into() });
+        theme.set_id("end", ElementStyle {
+            block: Some(BlockStyle { alignment: Some(Alignment::Right), ..BlockStyle::default() }),
+            ..ElementStyle::default()
+        });
+
+        let widget = HtmlWidget::build(html, Some(&theme), None).0;
+
+        assert_eq!(widget.paragraph, Paragraph::new(vec![
+            Line::from(vec![
+                Span::from("> ").italic(),
+                Span::from("Read ").italic(),
+                Span::from("this").italic().blue(),
+                Span::from(" ").italic(),
+                Span::from("now").italic().red(),
+                Span::from("!").italic().red().bold(),
+            ]),
+            Line::raw("\u{00A0}"),
+            Line::raw("\u{00A0}"),
+            Line::from(vec![Span::from("done")]).right_aligned(),
+        ]).wrap(Wrap { trim: false }));
+        assert_eq!(widget.links, vec![String::from("/x")]);
+    }
+
+    #[test]
+    fn style_attributes_color_and_hide_text() {
+        let html = r#"<p style="color: #c00; background-color: rgb(0, 0, 255) !important">red <span style="font-weight:700;text-decoration:underline line-through">mark</span></p>
+<h2 style="font-weight: normal; color: teal">Plain</h2><p>a<span style="display:none">gone <a href="/x">x</a></span><b style="visibility: hidden">blank</b><a href="/y">y</a></p>"#;
+
+        let widget = construct_widget(html);
+
+        let red = Style::new().fg(Color::Rgb(0xcc, 0, 0)).bg(Color::Rgb(0, 0, 255));
+        assert_eq!(widget.paragraph, Paragraph::new(vec![
+            Line::from(vec![Span::styled("red ", red), Span::styled("mark", red.bold().underlined().crossed_out())]),
+            // The margins below the paragraph and above the heading collapse.
+            Line::raw("\u{00A0}"),
+            Line::from(vec![Span::styled("Plain", Style::new().not_bold().cyan())]).underlined(),
+            Line::from(vec![Span::from("a"), Span::from("     "), Span::from("y").underlined(), Span::from("[1]").bold()]),
+            Line::raw("\u{00A0}"),
+        ]).wrap(Wrap { trim: false }));
+        assert_eq!(widget.links, vec![String::from("/y")]);
+    }
+
+    #[test]
+    fn style_elements_cascade_by_importance_specificity_and_order() {
+        let html = r#"<head><style>
+/* comments, at-rules and unsupported selectors are skipped */
+@import url(x.css);
+@media (max-width: 600px) { p { color: red } }
+p, a:hover { color: #00f; font-style: italic }
+.note { color: #0f0 }
+#main .note { color: #f00 }
+.note { font-weight: bold }
+p.quiet { color: #888 !important }
+div > p:first-child { text-decoration: underline }
+[data-hide] { display: none }
+</style></head><div id="main"><p class="note quiet" style="color: #fff">one</p><p class="note" style="font-style: normal">two</p><p data-hide>three</p></div>"#;
+
+        let widget = construct_widget(html);
+
+        let paragraph = |text: &'static str, style: Style| vec![Line::from(vec![Span::styled(text, style)]), Line::raw("\u{00A0}")];
+        assert_eq!(widget.paragraph, Paragraph::new([
+            paragraph("one", Style::new().fg(Color::Rgb(0x88, 0x88, 0x88)).italic().bold().underlined()),
+            paragraph("two", Style::new().fg(Color::Rgb(0xff, 0, 0)).not_italic().bold()),
+        ].concat()).wrap(Wrap { trim: false }));
+    }
+
+    #[test]
+    fn hidden_elements_hide_their_markers_and_rules() {
+        let rows = |html: &str| -> Vec<String> {
+            let widget = construct_widget(html);
+            let mut buffer = Buffer::empty(Rect::new(0, 0, 12, 4));
+            (&widget).render(buffer.area, &mut buffer);
+            (0..4).map(|y| (0..12).map(|x| buffer[(x, y)].symbol()).collect::<String>().trim_end().to_owned()).collect()
+        };
+
+        // Blanks as wide as the link and its marker.
+        assert_eq!(rows(r#"A <a href=x style="visibility:hidden">link</a> B"#), vec![format!("A{}B", " ".repeat(9)), "".into(), "".into(), "".into()]);
+        for html in [
+            r#"<ul style="visibility:hidden"><li>one</ul>"#,
+            r#"<h1 style="visibility:hidden">Title</h1>"#,
+            r#"<blockquote style="visibility:hidden">q</blockquote>"#,
+            r#"<table style="visibility:hidden"><tr><td>t</table>"#,
+        ] {
+            assert_eq!(rows(html), vec![""; 4], "{}", html);
+        }
+    }
+
+    #[test]
+    fn end_tags_undo_the_style_of_their_start_tags() {
+        // The rule added inside the paragraph doesn't change what its end
+        // tag takes off.
+        let html = "<style>p{color:red}</style><p>a<style>p{color:blue}</style></p><div>after</div>";
+        let widget = construct_widget(html);
+        assert_eq!(widget.lines[2], Line::from(vec![Span::from("after")]));
+
+        let html = r#"<div style="visibility:hidden"><p>x<style>p{visibility:visible}</style></p>still hidden</div>"#;
+        let widget = construct_widget(html);
+        assert!(widget.lines.iter().all(|line| !line.to_string().contains("still")));
+    }
+
+    #[test]
+    fn colors_are_fitted_to_the_palette() {
+        let html = r##"<body bgcolor="#222"><font color=333333>dim</font> <font color="hsl(120, 100%, 40%)">green</font></body>"##;
+
+        let widget = construct_widget(html);
+        assert_eq!(widget.paragraph, Paragraph::new(vec![Line::from(vec![
+            Span::styled("dim", Style::new().fg(Color::Rgb(0x33, 0x33, 0x33)).bg(Color::Rgb(0x22, 0x22, 0x22))),
+            Span::styled(" ", Style::new().bg(Color::Rgb(0x22, 0x22, 0x22))),
+            Span::styled("green", Style::new().fg(Color::Rgb(0, 204, 0)).bg(Color::Rgb(0x22, 0x22, 0x22))),
+        ])]).wrap(Wrap { trim: false }));
+
+        // Dim text on the dark background is lightened, then the colors go
+        // to the 16 the terminal has.
+        let theme = Theme { palette: Palette { depth: ColorDepth::Ansi16, background: Some(Background::Dark) }, ..Theme::default() };
+        let widget = HtmlWidget::build(html, Some(&theme), None).0;
+        assert_eq!(widget.paragraph, Paragraph::new(vec![Line::from(vec![
+            Span::styled("dim", Style::new().fg(Color::DarkGray).bg(Color::Black)),
+            Span::styled(" ", Style::new().bg(Color::Black)),
+            Span::styled("green", Style::new().fg(Color::LightGreen).bg(Color::Black)),
+        ])]).wrap(Wrap { trim: false }));
+
+        let theme = Theme { palette: Palette { depth: ColorDepth::Monochrome, background: None }, ..Theme::default() };
+        let widget = HtmlWidget::build(r#"<b style="color: red">x</b><a href="/">y</a>"#, Some(&theme), None).0;
+        assert_eq!(widget.paragraph, Paragraph::new(vec![Line::from(vec![
+            Span::from("x").bold(),
+            Span::from("y").underlined(),
+            Span::from("[1]").bold(),
+        ])]).wrap(Wrap { trim: false }));
+    }
+
     #[test]
     fn images_are_properly_indexed_and_stored() {
         let html = r#"
@@ -424,7 +1743,7 @@ Google"#;
         <img src="/home/garrett/Documents/image3.jpg"></img>
     "#;
 
-        let widget = construct_widget(html.into());
+        let widget = construct_widget(html);
 
         assert!(widget.images.len() == 3);
         assert!(widget.images[0] == "/home/garrett/Documents/image1.jpg" );
@@ -441,8 +1760,8 @@ Google"#;
                     let html = r#"
  <p>Breht reads and reacts to "A Letter To The American People" written by Masoud Pezeschkian, the Iranian president, as a ground invasion of some sort seems imminent. Check out our new design in collaboration with <em>Goods for the People</em> <a href="https://goodsforthepeople.com/">HERE</a></p> <p> </p> <p> -------------------------------------------------------------------------------</p> <p>Support Rev Left and get bonus episodes on <a href= "https://www.patreon.com/revleftradio">Patreon</a></p> <p>Make a one-time donation to Rev Left at <a href= "https://buymeacoffee.com/revleftradio">BuyMeACoffee.com/revleftradio</a></p> <p>Follow RLR on IG <a href= "https://www.instagram.com/rev_left_radio_official/" rel= "noopener noreferrer nofollow">HERE</a></p> <p>Learn more about Rev Left <a href= "https://revleftradio.com/">HERE</a></p>
  "#;
-                    let html_widget = HtmlWidget::new(String::from(html));
-                    frame.render_widget(html_widget.get_paragraph(), frame.area());
+                    let html_widget = HtmlWidget::new(html);
+                    frame.render_widget(&html_widget, frame.area());
                 })
             .unwrap();
             assert_snapshot!(terminal.backend());
@@ -0,0 +1,339 @@
+use std::{collections::HashSet, iter::Peekable, str::Chars};
+
+use crate::tuihtml::{dom::{Document, NodeId}, html::{HtmlElement, HtmlTag}};
+
+// A CSS selector of compound selectors joined by descendant and child
+// combinators, like `div.post > p:first-child a[href^="http"]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selector {
+    // What the element itself has to match.
+    subject: Compound,
+    // The compounds to the left of the subject, nearest first, each with the
+    // combinator joining it to the one on its right.
+    ancestors: Vec<(Combinator, Compound)>,
+}
+
+// Counts of ids, of classes, attributes and pseudo-classes, and of types.
+// Rules with greater specificity win.
+pub type Specificity = (usize, usize, usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Combinator {
+    Descendant,
+    Child,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Compound {
+    tag: Option<HtmlTag>,
+    ids: Vec<String>,
+    classes: Vec<String>,
+    attributes: Vec<AttributeSelector>,
+    first_child: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct AttributeSelector {
+    name: String,
+    value: Option<(AttributeOperator, String)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AttributeOperator {
+    // [a=v]
+    Equals,
+    // [a~=v], one of the whitespace separated words.
+    Includes,
+    // [a|=v], v or v followed by a hyphen.
+    DashMatch,
+    // [a^=v]
+    Prefix,
+    // [a$=v]
+    Suffix,
+    // [a*=v]
+    Substring,
+}
+
+impl Selector {
+    // Parses one selector, not a comma separated list. Returns None for
+    // selectors that are malformed or use what isn't supported, like the
+    // sibling combinators, pseudo-elements or other pseudo-classes, since
+    // these could never be matched correctly.
+    pub fn parse(selector: &str) -> Option<Selector> {
+        let mut chars = selector.trim().chars().peekable();
+        let mut compounds = Vec::new();
+        let mut combinators = Vec::new();
+
+        loop {
+            compounds.push(parse_compound(&mut chars)?);
+
+            let whitespace = skip_whitespace(&mut chars);
+            match chars.peek() {
+                None => break,
+                Some('>') => {
+                    chars.next();
+                    skip_whitespace(&mut chars);
+                    combinators.push(Combinator::Child);
+                },
+                Some(_) if whitespace => combinators.push(Combinator::Descendant),
+                Some(_) => return None,
+            }
+        }
+
+        let subject = compounds.pop()?;
+        let ancestors = combinators.into_iter().rev().zip(compounds.into_iter().rev()).collect();
+        Some(Selector { subject, ancestors })
+    }
+
+    pub fn specificity(&self) -> Specificity {
+        std::iter::once(&self.subject)
+            .chain(self.ancestors.iter().map(|(_, compound)| compound))
+            .fold((0, 0, 0), |(ids, classes, types), compound| (
+                ids + compound.ids.len(),
+                classes + compound.classes.len() + compound.attributes.len() + usize::from(compound.first_child),
+                types + usize::from(compound.tag.is_some()),
+            ))
+    }
+
+    pub fn matches(&self, document: &Document, id: NodeId) -> bool {
+        self.subject.matches(document, id) && matches_ancestors(document, id, &self.ancestors, &mut HashSet::new())
+    }
+}
+
+// Whether the compounds left of one matched at `id` match further up the
+// tree, trying every ancestor a descendant combinator could stand for.
+// Where the rest of the compounds failed to match is remembered, keyed by
+// how many of them are left, so no ancestor is tried twice for the same
+// compounds.
+fn matches_ancestors(document: &Document, id: NodeId, ancestors: &[(Combinator, Compound)], failed: &mut HashSet<(usize, NodeId)>) -> bool {
+    let Some(((combinator, compound), rest)) = ancestors.split_first() else {
+        return true;
+    };
+    if failed.contains(&(ancestors.len(), id)) {
+        return false;
+    }
+
+    let matched = match combinator {
+        Combinator::Child => document.parent(id)
+            .is_some_and(|parent| compound.matches(document, parent) && matches_ancestors(document, parent, rest, failed)),
+        Combinator::Descendant => document.ancestors(id)
+            .any(|ancestor| compound.matches(document, ancestor) && matches_ancestors(document, ancestor, rest, failed)),
+    };
+    if !matched {
+        failed.insert((ancestors.len(), id));
+    }
+    matched
+}
+
+impl Compound {
+    fn matches(&self, document: &Document, id: NodeId) -> bool {
+        let Some(element) = document.node(id).element() else {
+            return false;
+        };
+
+        self.tag.as_ref().is_none_or(|tag| *tag == element.tag)
+            && self.ids.iter().all(|id| element.id() == Some(id.as_str()))
+            && self.classes.iter().all(|class| element.has_class(class))
+            && self.attributes.iter().all(|attribute| attribute.matches(element))
+            && (!self.first_child || is_first_child(document, id))
+    }
+}
+
+impl AttributeSelector {
+    fn matches(&self, element: &HtmlElement) -> bool {
+        let Some(actual) = element.attr(&self.name) else {
+            return false;
+        };
+
+        match &self.value {
+            None => true,
+            Some((operator, value)) => match operator {
+                AttributeOperator::Equals => actual == value,
+                AttributeOperator::Includes => actual.split_ascii_whitespace().any(|word| word == value),
+                AttributeOperator::DashMatch => actual == value || actual.strip_prefix(value.as_str()).is_some_and(|rest| rest.starts_with('-')),
+                AttributeOperator::Prefix => !value.is_empty() && actual.starts_with(value.as_str()),
+                AttributeOperator::Suffix => !value.is_empty() && actual.ends_with(value.as_str()),
+                AttributeOperator::Substring => !value.is_empty() && actual.contains(value.as_str()),
+            },
+        }
+    }
+}
+
+// Elements are counted, text and comments before it are not.
+fn is_first_child(document: &Document, id: NodeId) -> bool {
+    let mut sibling = document.prev_sibling(id);
+
+    while let Some(prev) = sibling {
+        if document.node(prev).element().is_some() {
+            return false;
+        }
+        sibling = document.prev_sibling(prev);
+    }
+
+    true
+}
+
+fn parse_compound(chars: &mut Peekable<Chars>) -> Option<Compound> {
+    let mut compound = Compound::default();
+    let mut empty = true;
+
+    if chars.peek() == Some(&'*') {
+        chars.next();
+        empty = false;
+    } else if chars.peek().is_some_and(|c| is_ident_char(*c)) {
+        compound.tag = Some(HtmlTag::from_string(&ident(chars)?));
+        empty = false;
+    }
+
+    loop {
+        match chars.peek() {
+            Some('.') => {
+                chars.next();
+                compound.classes.push(ident(chars)?);
+            },
+            Some('#') => {
+                chars.next();
+                compound.ids.push(ident(chars)?);
+            },
+            Some('[') => {
+                chars.next();
+                compound.attributes.push(parse_attribute(chars)?);
+            },
+            Some(':') => {
+                chars.next();
+                match ident(chars)?.to_ascii_lowercase().as_str() {
+                    "first-child" => compound.first_child = true,
+                    _ => return None,
+                }
+            },
+            _ => break,
+        }
+        empty = false;
+    }
+
+    match empty {
+        true => None,
+        false => Some(compound),
+    }
+}
+
+// The inside of `[name]` or `[name op value]`, after the opening bracket.
+fn parse_attribute(chars: &mut Peekable<Chars>) -> Option<AttributeSelector> {
+    skip_whitespace(chars);
+    let name = ident(chars)?.to_ascii_lowercase();
+    skip_whitespace(chars);
+
+    let operator = match chars.next()? {
+        ']' => return Some(AttributeSelector { name, value: None }),
+        '=' => AttributeOperator::Equals,
+        operator => {
+            let operator = match operator {
+                '~' => AttributeOperator::Includes,
+                '|' => AttributeOperator::DashMatch,
+                '^' => AttributeOperator::Prefix,
+                '$' => AttributeOperator::Suffix,
+                '*' => AttributeOperator::Substring,
+                _ => return None,
+            };
+            match chars.next()? {
+                '=' => operator,
+                _ => return None,
+            }
+        },
+    };
+    skip_whitespace(chars);
+
+    let value = match chars.peek()? {
+        quote @ ('"' | '\'') => {
+            let quote = *quote;
+            chars.next();
+            let value: String = chars.by_ref().take_while(|c| *c != quote).collect();
+            value
+        },
+        _ => ident(chars)?,
+    };
+    skip_whitespace(chars);
+
+    match chars.next()? {
+        ']' => Some(AttributeSelector { name, value: Some((operator, value)) }),
+        _ => None,
+    }
+}
+
+fn ident(chars: &mut Peekable<Chars>) -> Option<String> {
+    let mut ident = String::new();
+    while let Some(c) = chars.next_if(|c| is_ident_char(*c)) {
+        ident.push(c);
+    }
+
+    match ident.is_empty() {
+        true => None,
+        false => Some(ident),
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_' || !c.is_ascii()
+}
+
+// Returns whether there was any.
+fn skip_whitespace(chars: &mut Peekable<Chars>) -> bool {
+    let mut skipped = false;
+    while chars.next_if(|c| c.is_whitespace()).is_some() {
+        skipped = true;
+    }
+    skipped
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tuihtml::{html::HtmlTag, parser::parse_document, selector::Selector};
+
+    #[test]
+    fn selectors_match_by_type_class_id_attribute_and_position() {
+        let document = parse_document(r#"<div id="post" class="entry wide"><p lang="en-GB">one <a href="https://x.org/a.pdf">a</a></p><p><span><a href="/b">b</a></span></p></div>"#);
+        let links: Vec<_> = document.find_all(HtmlTag::A).collect();
+        let matching = |selector: &str| -> Vec<bool> {
+            let selector = Selector::parse(selector).unwrap();
+            links.iter().map(|id| selector.matches(&document, *id)).collect()
+        };
+
+        assert_eq!(matching("a"), vec![true, true]);
+        assert_eq!(matching("div#post.wide a"), vec![true, true]);
+        assert_eq!(matching("p > a"), vec![true, false]);
+        assert_eq!(matching("div > p:first-child a"), vec![true, false]);
+        assert_eq!(matching(".entry p span > *"), vec![false, true]);
+        assert_eq!(matching("[lang|=en] [href^=\"https\"][href$='.pdf']"), vec![true, false]);
+        assert_eq!(matching("[class~=entry] a[href*=b]"), vec![false, true]);
+        assert_eq!(matching("p.entry a"), vec![false, false]);
+
+        // Tags the parser does not know are told apart by name.
+        let document = parse_document("<x-card><p>one</p></x-card><x-note><p>two</p></x-note>");
+        let selector = Selector::parse("X-Card p").unwrap();
+        let matches: Vec<bool> = document.find_all(HtmlTag::P).map(|id| selector.matches(&document, id)).collect();
+        assert_eq!(matches, vec![true, false]);
+    }
+
+    #[test]
+    fn descendant_selectors_match_deep_trees_quickly() {
+        // Every div could stand for every one of the selector's, which takes
+        // exponential time to rule out without remembering what failed: this
+        // test would not finish.
+        let html = format!("{}<p>x</p>{}", "<div>".repeat(200), "</div>".repeat(200));
+        let document = parse_document(&html);
+        let p = document.find(HtmlTag::P).unwrap();
+
+        assert!(!Selector::parse("span div div div div div div p").unwrap().matches(&document, p));
+        assert!(Selector::parse("div div div div div div p").unwrap().matches(&document, p));
+    }
+
+    #[test]
+    fn unsupported_selectors_are_rejected() {
+        for selector in ["a:hover", "p::before", "h1 + p", "h1 ~ p", "a[href", "", ".", "p >"] {
+            assert_eq!(Selector::parse(selector), None, "{}", selector);
+        }
+
+        assert_eq!(Selector::parse("ul#nav li.item:first-child a[href]").unwrap().specificity(), (1, 3, 3));
+        assert_eq!(Selector::parse("*").unwrap().specificity(), (0, 0, 0));
+    }
+}
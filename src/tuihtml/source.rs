@@ -0,0 +1,68 @@
+// A location in the source HTML. `offset` is in bytes, `line` and `column`
+// start at 1 and `column` counts characters, not bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SourcePosition {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Default for SourcePosition {
+    fn default() -> Self {
+        SourcePosition {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+impl SourcePosition {
+    pub fn advance(&mut self, char: char) {
+        self.offset += char.len_utf8();
+
+        match char {
+            '\n' => {
+                self.line += 1;
+                self.column = 1;
+            },
+            _ => self.column += 1,
+        }
+    }
+}
+
+// A half-open range of the source HTML, from `start` up to but not
+// including `end`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct SourceSpan {
+    pub start: SourcePosition,
+    pub end: SourcePosition,
+}
+
+impl SourceSpan {
+    pub fn new(start: SourcePosition, end: SourcePosition) -> Self {
+        SourceSpan { start, end }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end.offset - self.start.offset
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start.offset == self.end.offset
+    }
+
+    // The smallest span covering both `self` and `other`.
+    pub fn union(&self, other: &SourceSpan) -> SourceSpan {
+        SourceSpan {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+
+    // The slice of `source` this span covers, if `source` is the text the
+    // span was recorded from.
+    pub fn slice<'a>(&self, source: &'a str) -> Option<&'a str> {
+        source.get(self.start.offset..self.end.offset)
+    }
+}
@@ -0,0 +1,344 @@
+use ratatui::{text::Line, widgets::{Paragraph, Wrap}};
+
+use crate::tuihtml::{dom::{Document, NodeEdge}, options::{Limit, ParseOptions}, parser::{Layout, LayoutCheckpoint}, source::SourceSpan, theme::Theme, tokenizer::{HtmlTokenizer, Token, TokenizerState}, treebuilder::TreeBuilder, widget::{paragraph_for_width, HtmlWidget}};
+
+// Builds an HtmlWidget from HTML that arrives a chunk at a time. Tokens that
+// could still change with more input, like a tag cut off halfway, are held
+// back until the next chunk completes them, and only the lines after the
+// last part of the tree that can no longer change are laid out again.
+#[derive(Debug)]
+pub struct HtmlStream<'a> {
+    // Input that has not been made into tokens yet.
+    pending: String,
+    // Where tokenizing stopped, None until the first token is taken.
+    tokenizer: Option<TokenizerState>,
+    tree_builder: TreeBuilder,
+    layout: Layout<'a>,
+    // The last settled edge of the tree and the layout right after it.
+    checkpoint: Option<(NodeEdge, LayoutCheckpoint<'a>)>,
+    options: ParseOptions,
+    // Bytes of input taken so far, and tokens made of them.
+    received: usize,
+    tokens: usize,
+    // The limits that cut the document short so far. Past the size, token
+    // or line limit the rest of the input is ignored.
+    limits: Vec<Limit>,
+}
+
+impl<'a> HtmlStream<'a> {
+    pub fn new() -> Self {
+        Self::with_theme(&Theme::default())
+    }
+
+    pub fn with_theme(theme: &Theme) -> Self {
+        Self::with_options(theme, &ParseOptions::default())
+    }
+
+    // Keeps to the limits of `options`, like HtmlWidget::build.
+    pub fn with_options(theme: &Theme, options: &ParseOptions) -> Self {
+        let mut tree_builder = TreeBuilder::new();
+        tree_builder.set_max_depth(options.max_depth);
+
+        HtmlStream {
+            pending: String::new(),
+            tokenizer: None,
+            tree_builder,
+            layout: Layout::new(theme.clone()),
+            checkpoint: None,
+            options: *options,
+            received: 0,
+            tokens: 0,
+            limits: Vec::new(),
+        }
+    }
+
+    pub fn push_str(&mut self, chunk: &str) {
+        if self.limits.iter().any(|limit| matches!(limit, Limit::DocumentSize | Limit::Tokens | Limit::Lines)) {
+            return;
+        }
+
+        let mut end = chunk.len().min(self.options.max_document_size.saturating_sub(self.received));
+        while !chunk.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end < chunk.len() {
+            self.limits.push(Limit::DocumentSize);
+        }
+        self.received += end;
+        self.pending.push_str(&chunk[..end]);
+
+        self.tokenize(false);
+        self.relayout();
+        self.layout.flush();
+        cut_lines(&mut self.layout, self.options.max_lines, &mut self.limits);
+    }
+
+    // Ends the input, closing anything still open. Also returns the limits
+    // that cut the document short.
+    pub fn finish(mut self) -> (HtmlWidget<'a>, Vec<Limit>) {
+        self.tokenize(true);
+
+        if self.tree_builder.depth_exceeded().is_some() {
+            self.limits.push(Limit::Depth);
+        }
+        let document = self.tree_builder.finish();
+        self.layout = relayout(self.layout, &mut self.checkpoint, &document, None, self.options.max_lines);
+        self.layout.flush();
+        cut_lines(&mut self.layout, self.options.max_lines, &mut self.limits);
+
+        // In the order HtmlWidget::build gives them.
+        let limits = [Limit::DocumentSize, Limit::Tokens, Limit::Depth, Limit::Attributes, Limit::Lines]
+            .into_iter()
+            .filter(|limit| self.limits.contains(limit))
+            .collect();
+
+        (self.layout.into_widget(document), limits)
+    }
+
+    pub fn get_paragraph(&self) -> Paragraph<'a> {
+        Paragraph::new(self.layout.lines().to_vec()).wrap(Wrap { trim: false })
+    }
+
+    // As HtmlWidget::get_paragraph_for_width.
+    pub fn get_paragraph_for_width(&self, width: u16) -> Paragraph<'a> {
+        paragraph_for_width(self.layout.lines(), self.layout.line_layouts(), self.layout.rules(), self.layout.tables(), width)
+    }
+
+    pub fn get_lines(&self) -> &[Line<'a>] {
+        self.layout.lines()
+    }
+
+    pub fn get_links(&self) -> Vec<String> {
+        self.layout.links().to_vec()
+    }
+
+    pub fn get_images(&self) -> Vec<String> {
+        self.layout.images().to_vec()
+    }
+
+    pub fn get_document(&self) -> &Document {
+        self.tree_builder.document()
+    }
+
+    pub fn get_line_source(&self, line: usize) -> Option<SourceSpan> {
+        self.layout.line_sources().get(line).copied().flatten()
+    }
+
+    // Feeds every complete token in `pending` to the tree builder. At the
+    // end of the input everything left is complete.
+    fn tokenize(&mut self, eof: bool) {
+        let mut tokenizer = match self.tokenizer {
+            Some(state) => HtmlTokenizer::resume(self.pending.chars(), state),
+            None => HtmlTokenizer::new(self.pending.chars()),
+        };
+        tokenizer.set_max_attributes(self.options.max_attributes);
+        let base = self.tokenizer.map_or(0, |state| state.position.offset);
+        let mut committed = None;
+
+        loop {
+            let before = tokenizer.state();
+            let (token, span) = tokenizer.next_with_span();
+            if token == Token::Eof {
+                break;
+            }
+
+            if !eof && tokenizer.is_exhausted() {
+                let source = &self.pending[span.start.offset - base..span.end.offset - base];
+                if !is_complete_text(&token, source, &before) {
+                    break;
+                }
+            }
+            if self.tokens == self.options.max_tokens {
+                if !self.limits.contains(&Limit::Tokens) {
+                    self.limits.push(Limit::Tokens);
+                }
+                break;
+            }
+            self.tokens += 1;
+
+            self.tree_builder.process(token, span);
+            committed = Some(tokenizer.state());
+        }
+        if tokenizer.attributes_dropped() && !self.limits.contains(&Limit::Attributes) {
+            self.limits.push(Limit::Attributes);
+        }
+        drop(tokenizer);
+
+        if let Some(state) = committed {
+            self.pending.drain(..state.position.offset - base);
+            self.tokenizer = Some(state);
+        }
+    }
+
+    fn relayout(&mut self) {
+        let layout = std::mem::take(&mut self.layout);
+        let settled = self.tree_builder.settled_edge();
+        self.layout = relayout(layout, &mut self.checkpoint, self.tree_builder.document(), Some(settled), self.options.max_lines);
+    }
+}
+
+impl Default for HtmlStream<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Text cut off by the end of a chunk can be taken as it is, since the rest
+// becomes more text in the same node, unless it ends in what may be the
+// start of markup or of a character reference, or is raw text that has to
+// stay in raw text mode.
+fn is_complete_text(token: &Token, source: &str, before: &TokenizerState) -> bool {
+    if !matches!(token, Token::Text(_)) || before.in_raw_text() {
+        return false;
+    }
+    if source.ends_with('<') {
+        return false;
+    }
+
+    match source.rfind('&') {
+        Some(i) => !source[i + 1..].chars().all(|c| c.is_ascii_alphanumeric() || c == '#'),
+        None => true,
+    }
+}
+
+// Drops the lines past `max_lines`, noting the limit.
+fn cut_lines(layout: &mut Layout, max_lines: usize, limits: &mut Vec<Limit>) {
+    if layout.lines().len() > max_lines {
+        layout.truncate(max_lines);
+        if !limits.contains(&Limit::Lines) {
+            limits.push(Limit::Lines);
+        }
+    }
+}
+
+// Lays out the document from the checkpoint on, moving the checkpoint up to
+// `settled` once the walk passes it. The walk stops once there are more than
+// `max_lines` lines.
+fn relayout<'a>(
+    mut layout: Layout<'a>,
+    checkpoint: &mut Option<(NodeEdge, LayoutCheckpoint<'a>)>,
+    document: &Document,
+    settled: Option<NodeEdge>,
+    max_lines: usize,
+) -> Layout<'a> {
+    let (start, skip) = match checkpoint {
+        Some((edge, state)) => {
+            layout.restore(state);
+            (*edge, 1)
+        },
+        None => {
+            layout.reset();
+            (NodeEdge::Start(document.root()), 0)
+        },
+    };
+
+    for edge in document.traverse_from(document.root(), start).skip(skip) {
+        layout.visit(document, edge);
+        if layout.lines().len() > max_lines {
+            break;
+        }
+        // Not at the start of an element, whose source span only covers its
+        // start tag until it is closed, though the lines laid out there take
+        // it as it is.
+        if Some(edge) == settled && matches!(edge, NodeEdge::End(_)) && layout.can_checkpoint() {
+            *checkpoint = Some((edge, layout.checkpoint()));
+        }
+    }
+
+    layout
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tuihtml::{
+        html::HtmlTag,
+        options::{Limit, ParseOptions},
+        parser::construct_widget,
+        stream::HtmlStream,
+        theme::Theme,
+        widget::HtmlWidget,
+    };
+
+    #[test]
+    fn chunked_input_renders_like_whole_input() {
+        let html = "<h1>Title &amp; more</h1>\n<p>caf&eacute; <b>bold <i>both</b> italic</i> done</p>\r\n\
+            <ul><li>one<li><a href=\"https://example.com\">two</a></ul>\n\
+            <script>if (a < b) {}</script><ol start=3><li>x</li></ol>\
+            <table><tr><th>k<td><a href=\"b\">v</a> w</table><blockquote>q <cite>c</cite> </blockquote><img src=\"a.png\">tail &lt; end";
+        let whole = construct_widget(html);
+
+        for size in 1..=8 {
+            let mut stream = HtmlStream::new();
+            let chars: Vec<char> = html.chars().collect();
+            for chunk in chars.chunks(size) {
+                stream.push_str(&chunk.iter().collect::<String>());
+            }
+            let (widget, limits) = stream.finish();
+            assert!(limits.is_empty());
+
+            assert_eq!(widget.get_paragraph(), whole.get_paragraph(), "chunk size {}", size);
+            assert_eq!(widget.get_document(), whole.get_document(), "chunk size {}", size);
+            assert_eq!(widget.line_sources, whole.line_sources, "chunk size {}", size);
+            assert_eq!(widget.rules, whole.rules, "chunk size {}", size);
+            assert_eq!(widget.line_layouts, whole.line_layouts, "chunk size {}", size);
+            assert_eq!(widget.tables, whole.tables, "chunk size {}", size);
+            assert_eq!(widget.get_links(), whole.get_links());
+            assert_eq!(widget.get_images(), whole.get_images());
+        }
+    }
+
+    #[test]
+    fn half_finished_markup_waits_for_the_next_chunk() {
+        let text = |stream: &HtmlStream| -> Vec<String> {
+            stream.get_lines().iter().map(|line| line.to_string()).collect()
+        };
+
+        let mut stream = HtmlStream::new();
+        stream.push_str("<p>Hello <b>wor");
+        // Elements still open are laid out as if they ended here.
+        assert_eq!(text(&stream), vec!["Hello wor", "\u{a0}"]);
+
+        // Text ending in what may be a character reference is held back.
+        stream.push_str("ld</b> caf&eac");
+        assert_eq!(text(&stream), vec!["Hello world", "\u{a0}"]);
+
+        stream.push_str("ute;</p><h2 cla");
+        assert_eq!(text(&stream), vec!["Hello world caf\u{e9}", "\u{a0}"]);
+        assert!(stream.get_document().find(HtmlTag::H2).is_none());
+
+        stream.push_str("ss=\"x\">Next");
+        assert_eq!(stream.get_document().find(HtmlTag::H2).and_then(|id| stream.get_document().attr(id, "class")), Some("x"));
+        assert!(text(&stream).iter().any(|line| line.contains("Next")));
+    }
+
+    #[test]
+    fn limits_stop_the_stream_and_are_reported() {
+        let stream = |options: ParseOptions, chunks: &[&str]| {
+            let mut stream = HtmlStream::with_options(&Theme::default(), &options);
+            for chunk in chunks {
+                stream.push_str(chunk);
+            }
+            stream.finish()
+        };
+        let text = |widget: &HtmlWidget| widget.get_document().text_content(widget.get_document().root());
+        let options = ParseOptions::default();
+
+        // Whatever comes past the size limit is ignored, later chunks too.
+        let (widget, limits) = stream(ParseOptions { max_document_size: 13, ..options }, &["<p>one</p>", "<p>two</p>", "<p>three</p>"]);
+        assert_eq!(limits, vec![Limit::DocumentSize]);
+        assert_eq!(text(&widget), "one");
+
+        let (widget, limits) = stream(ParseOptions { max_tokens: 3, ..options }, &["<p>one</p>", "<p>two</p>"]);
+        assert_eq!(limits, vec![Limit::Tokens]);
+        assert_eq!(text(&widget), "one");
+
+        let (widget, limits) = stream(ParseOptions { max_lines: 3, ..options }, &["<p>one</p><p>two</p>", "<p>three</p>"]);
+        assert_eq!(limits, vec![Limit::Lines]);
+        assert_eq!(widget.line_sources.len(), 3);
+
+        let (widget, limits) = stream(ParseOptions { max_depth: 4, max_attributes: 1, ..options }, &["<div a=1 b=2><div><div>", "<div><div>x"]);
+        assert_eq!(limits, vec![Limit::Depth, Limit::Attributes]);
+        let div = widget.get_document().find(HtmlTag::DIV).unwrap();
+        assert_eq!(widget.get_document().node(div).element().unwrap().attributes.len(), 1);
+    }
+}
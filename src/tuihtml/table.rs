@@ -0,0 +1,570 @@
+use ratatui::{layout::Alignment, style::Style, symbols::line, text::{Line, Span}};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::tuihtml::{layout::LaidOut, theme::TableBorder};
+
+// A table whose cells have been laid out, kept so that its columns can be
+// sized to the width it is rendered at.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Table<'a> {
+    caption: Option<LaidOut<'a>>,
+    cells: Vec<Cell<'a>>,
+    rows: usize,
+    columns: usize,
+    // None to leave the borders out, with the columns two blanks apart.
+    border: Option<TableBorder>,
+    // Where the next cell of the last row goes, and for each column the
+    // first row that the cells placed so far leave free.
+    next_column: usize,
+    free_from: Vec<usize>,
+}
+
+// Where a table is among the lines of a layout, so that it can be laid out
+// again to fit the area the lines end up in.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TableLines<'a> {
+    pub(crate) line: usize,
+    pub(crate) lines: usize,
+    pub(crate) table: Table<'a>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Cell<'a> {
+    row: usize,
+    column: usize,
+    rows: usize,
+    columns: usize,
+    content: LaidOut<'a>,
+}
+
+// Borders drawn in blanks, for tables that are hidden.
+pub(crate) const BLANK_BORDER: line::Set = line::Set {
+    vertical: " ",
+    horizontal: " ",
+    top_right: " ",
+    top_left: " ",
+    bottom_right: " ",
+    bottom_left: " ",
+    vertical_left: " ",
+    vertical_right: " ",
+    horizontal_down: " ",
+    horizontal_up: " ",
+    cross: " ",
+};
+
+// Like browsers, cells are placed on a grid of at most this many columns,
+// and whatever would go past it is left out.
+const MAX_COLUMNS: usize = 1000;
+
+// The directions the border goes in from a position.
+const UP: u8 = 1;
+const DOWN: u8 = 2;
+const LEFT: u8 = 4;
+const RIGHT: u8 = 8;
+
+impl<'a> Table<'a> {
+    pub(crate) fn new(border: Option<TableBorder>) -> Self {
+        Table { border, ..Table::default() }
+    }
+
+    pub(crate) fn set_caption(&mut self, caption: LaidOut<'a>) {
+        self.caption = Some(caption);
+    }
+
+    pub(crate) fn add_row(&mut self) {
+        self.rows += 1;
+        self.next_column = 0;
+    }
+
+    // Whether the last row has a column left for another cell, which is
+    // worth knowing before laying out the cell's content.
+    pub(crate) fn has_room(&mut self) -> bool {
+        self.skip_covered();
+        self.next_column < MAX_COLUMNS
+    }
+
+    // Adds a cell to the last row, in the first column that no cell from
+    // the rows above spans down into.
+    pub(crate) fn add_cell(&mut self, content: LaidOut<'a>, rows: usize, columns: usize) {
+        if !self.has_room() {
+            return;
+        }
+        let (row, column) = (self.rows - 1, self.next_column);
+        let columns = columns.min(MAX_COLUMNS - column);
+
+        if self.free_from.len() < column + columns {
+            self.free_from.resize(column + columns, 0);
+        }
+        for free_from in &mut self.free_from[column..column + columns] {
+            *free_from = row.saturating_add(rows);
+        }
+        self.next_column = column + columns;
+
+        self.columns = self.columns.max(column + columns);
+        self.cells.push(Cell { row, column, rows, columns, content });
+    }
+
+    // Moves past the columns of the last row that cells from the rows above
+    // span down into. Cells are only ever added to the last row, left to
+    // right, so no column is stepped past twice.
+    fn skip_covered(&mut self) {
+        self.rows = self.rows.max(1);
+        let row = self.rows - 1;
+        while self.free_from.get(self.next_column).is_some_and(|free_from| *free_from > row) {
+            self.next_column += 1;
+        }
+    }
+
+    // The lines of the table in an area `width` columns wide. Columns get
+    // the room their content wants if there is enough of it, and otherwise
+    // share it out, never narrower than their longest words unless even
+    // those don't fit.
+    pub(crate) fn lines(&self, width: usize) -> Vec<Line<'a>> {
+        if self.columns == 0 {
+            return self.caption_lines(width);
+        }
+
+        let (gap, edge) = self.spacing();
+        let overhead = gap * (self.columns - 1) + 4 * edge;
+        let widths = self.column_widths(width.saturating_sub(overhead));
+
+        // Where the text of each column and row starts, with one more for
+        // where the table ends.
+        let mut xs = vec![2 * edge];
+        for width in &widths {
+            xs.push(xs[xs.len() - 1] + width + gap);
+        }
+        // Tables too wide for the area, even with every column as narrow as
+        // it can get, are cut off at its edge.
+        let table_width = (xs[self.columns] + 2 * edge - gap).min(width);
+
+        // Clipped to the rows there are, so rowspans can't run past the end.
+        // Cells past the edge of the area are left out.
+        let cells: Vec<(&Cell, usize, Vec<Line<'a>>)> = self.cells.iter().filter(|cell| xs[cell.column] < table_width).map(|cell| {
+            let rows = cell.rows.min(self.rows - cell.row);
+            let width = xs[cell.column + cell.columns] - xs[cell.column] - gap;
+            (cell, rows, trim_blank_lines(cell.content.fit(width)))
+        }).collect();
+
+        let mut heights = vec![1; self.rows];
+        let mut by_rows: Vec<_> = cells.iter().collect();
+        by_rows.sort_by_key(|(_, rows, _)| *rows);
+        for (cell, rows, lines) in by_rows {
+            let spanned = &mut heights[cell.row..cell.row + rows];
+            let height = spanned.iter().sum::<usize>() + edge * (rows - 1);
+            if let Some(last) = spanned.last_mut() {
+                *last += lines.len().saturating_sub(height);
+            }
+        }
+        let ys: Vec<usize> = std::iter::once(edge)
+            .chain(heights.iter().scan(edge, |y, height| {
+                *y += height + edge;
+                Some(*y)
+            }))
+            .collect();
+
+        let mut canvas = Canvas::new(table_width, ys[self.rows]);
+        for (cell, rows, lines) in &cells {
+            let (left, right) = (xs[cell.column], xs[cell.column + cell.columns] - gap);
+            let (top, bottom) = (ys[cell.row], ys[cell.row + rows] - edge);
+
+            if let Some(border) = self.border {
+                canvas.frame(left - 2, right + 1, top - 1, bottom, border.style);
+            }
+            for (y, line) in (top..bottom).zip(lines) {
+                canvas.write(left, y, right - left, line);
+            }
+        }
+
+        let mut lines = self.caption_lines(table_width);
+        lines.extend(canvas.into_lines(self.border.map(|border| border.symbols)));
+        lines
+    }
+
+    // The blanks between the columns, and whether there are borders
+    // around them.
+    fn spacing(&self) -> (usize, usize) {
+        match self.border {
+            Some(_) => (3, 1),
+            None => (2, 0),
+        }
+    }
+
+    fn column_widths(&self, available: usize) -> Vec<usize> {
+        let (gap, _) = self.spacing();
+        let mut min = vec![1; self.columns];
+        let mut max = vec![1; self.columns];
+
+        // Cells spanning one column go first, then those spanning more get
+        // whatever they are missing spread over their columns.
+        let mut cells: Vec<&Cell> = self.cells.iter().collect();
+        cells.sort_by_key(|cell| cell.columns);
+        for cell in cells {
+            let (cell_min, cell_max) = cell.content.widths();
+            let columns = cell.column..cell.column + cell.columns;
+            let gaps = gap * (cell.columns - 1);
+
+            grow(&mut min[columns.clone()], cell_min.saturating_sub(gaps));
+            grow(&mut max[columns], cell_max.saturating_sub(gaps));
+        }
+        for (max, min) in max.iter_mut().zip(&min) {
+            *max = (*max).max(*min);
+        }
+
+        let (total_min, total_max): (usize, usize) = (min.iter().sum(), max.iter().sum());
+        if total_max <= available {
+            max
+        } else if total_min <= available {
+            let room: Vec<usize> = max.iter().zip(&min).map(|(max, min)| max - min).collect();
+            min.iter().zip(share(available - total_min, &room)).map(|(min, extra)| min + extra).collect()
+        } else {
+            share(available.max(self.columns), &min).into_iter().map(|width| width.max(1)).collect()
+        }
+    }
+
+    // The caption, centered or aligned as its lines say over the width of
+    // the table.
+    fn caption_lines(&self, width: usize) -> Vec<Line<'a>> {
+        let Some(caption) = &self.caption else {
+            return Vec::new();
+        };
+
+        trim_blank_lines(caption.fit(width)).into_iter().map(|mut line| {
+            let room = width.saturating_sub(line.width());
+            let offset = match line.alignment {
+                Some(Alignment::Center) => room / 2,
+                Some(Alignment::Right) => room,
+                _ => 0,
+            };
+
+            // Padded to the width of the table, so it lines up with it
+            // however the table is aligned.
+            if offset > 0 {
+                line.spans.insert(0, Span::raw(" ".repeat(offset)));
+            }
+            if room > offset {
+                line.spans.push(Span::raw(" ".repeat(room - offset)));
+            }
+            line.alignment = None;
+            line
+        }).collect()
+    }
+}
+
+impl LaidOut<'_> {
+    // How narrow the lines can get, by their longest words, and how wide
+    // they are unwrapped. Rules fit whatever width they get.
+    fn widths(&self) -> (usize, usize) {
+        let mut min = 0;
+        let mut max = 0;
+
+        for (i, line) in self.lines.iter().enumerate() {
+            if self.rules.iter().any(|rule| rule.line == i) {
+                continue;
+            }
+
+            let indent = self.layouts.get(i).map_or(0, |layout| layout.indent);
+            let text: String = line.spans.iter().map(|span| span.content.as_ref()).collect();
+            let longest = text.split([' ', '\t']).map(UnicodeWidthStr::width).max().unwrap_or(0);
+
+            min = min.max(indent + longest);
+            max = max.max(line.width());
+        }
+
+        (min, max)
+    }
+}
+
+// Widens `columns` evenly until they add up to at least `width`.
+fn grow(columns: &mut [usize], width: usize) {
+    let missing = width.saturating_sub(columns.iter().sum());
+    let count = columns.len();
+
+    for (i, column) in columns.iter_mut().enumerate() {
+        *column += missing / count + usize::from(i < missing % count);
+    }
+}
+
+// Splits `total` in proportion to `weights`, handing what rounding leaves
+// over to the first of them.
+fn share(total: usize, weights: &[usize]) -> Vec<usize> {
+    let sum: usize = weights.iter().sum();
+    if sum == 0 {
+        let mut shares = vec![0; weights.len()];
+        grow(&mut shares, total);
+        return shares;
+    }
+
+    let mut shares: Vec<usize> = weights.iter().map(|weight| total * weight / sum).collect();
+    let mut left = total - shares.iter().sum::<usize>();
+    for (share, weight) in shares.iter_mut().zip(weights) {
+        if left > 0 && *weight > 0 {
+            *share += 1;
+            left -= 1;
+        }
+    }
+    shares
+}
+
+// Blank lines around the text of a cell, like the margin after its last
+// paragraph, take up no room.
+fn trim_blank_lines(mut lines: Vec<Line>) -> Vec<Line> {
+    let blank = |line: &Line| line.spans.iter().all(|span| span.content.trim().is_empty());
+
+    while lines.last().is_some_and(blank) {
+        lines.pop();
+    }
+    let leading = lines.iter().take_while(|line| blank(line)).count();
+    lines.drain(..leading);
+    lines
+}
+
+// The table drawn one position at a time, before it is made into lines.
+struct Canvas {
+    // None under the second column of a wide character.
+    glyphs: Vec<Vec<Option<(String, Style)>>>,
+    // The directions of the borders through each position.
+    borders: Vec<Vec<u8>>,
+    border_style: Style,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Canvas {
+            glyphs: vec![vec![Some((String::from(" "), Style::default())); width]; height],
+            borders: vec![vec![0; width]; height],
+            border_style: Style::default(),
+        }
+    }
+
+    // Draws the edges of a box, as much of it as is on the canvas. Boxes
+    // next to each other share an edge.
+    fn frame(&mut self, left: usize, right: usize, top: usize, bottom: usize, style: Style) {
+        self.border_style = style;
+        let width = self.borders[top].len();
+
+        for x in left..=right.min(width.saturating_sub(1)) {
+            let directions = if x > left { LEFT } else { 0 } | if x < right { RIGHT } else { 0 };
+            self.borders[top][x] |= directions;
+            self.borders[bottom][x] |= directions;
+        }
+        for y in top..=bottom {
+            let directions = if y > top { UP } else { 0 } | if y < bottom { DOWN } else { 0 };
+            for x in [left, right].into_iter().filter(|x| *x < width) {
+                self.borders[y][x] |= directions;
+            }
+        }
+    }
+
+    // Writes `line` from column `x` on, aligned within `width` columns and
+    // cut off past them.
+    fn write(&mut self, x: usize, y: usize, width: usize, line: &Line) {
+        let room = width.saturating_sub(line.width());
+        let end = (x + width).min(self.glyphs[y].len());
+        let mut x = x + match line.alignment {
+            Some(Alignment::Center) => room / 2,
+            Some(Alignment::Right) => room,
+            _ => 0,
+        };
+
+        for span in &line.spans {
+            let style = line.style.patch(span.style);
+
+            for c in span.content.chars() {
+                let char_width = c.width().unwrap_or(0);
+                if char_width == 0 {
+                    if let Some(Some((glyph, _))) = x.checked_sub(1).and_then(|x| self.glyphs[y].get_mut(x)) {
+                        glyph.push(c);
+                    }
+                    continue;
+                }
+                if x + char_width > end {
+                    return;
+                }
+
+                self.glyphs[y][x] = Some((c.to_string(), style));
+                for covered in 1..char_width {
+                    self.glyphs[y][x + covered] = None;
+                }
+                x += char_width;
+            }
+        }
+    }
+
+    fn into_lines<'a>(self, symbols: Option<line::Set>) -> Vec<Line<'a>> {
+        let border_style = self.border_style;
+
+        self.glyphs.into_iter().zip(self.borders).map(|(glyphs, borders)| {
+            let mut spans: Vec<Span<'a>> = Vec::new();
+
+            for (glyph, directions) in glyphs.into_iter().zip(borders) {
+                let (symbol, style) = match (symbols, directions) {
+                    (Some(symbols), 1..) => (junction(&symbols, directions).to_owned(), border_style),
+                    _ => match glyph {
+                        Some(glyph) => glyph,
+                        None => continue,
+                    },
+                };
+
+                match spans.last_mut() {
+                    Some(span) if span.style == style => span.content.to_mut().push_str(&symbol),
+                    _ => spans.push(Span::styled(symbol, style)),
+                }
+            }
+            Line::from(spans)
+        }).collect()
+    }
+}
+
+fn junction(symbols: &line::Set, directions: u8) -> &'static str {
+    let has = |direction: u8| directions & direction != 0;
+
+    match (has(UP), has(DOWN), has(LEFT), has(RIGHT)) {
+        (true, true, true, true) => symbols.cross,
+        (true, true, true, false) => symbols.vertical_left,
+        (true, true, false, true) => symbols.vertical_right,
+        (true, false, true, true) => symbols.horizontal_up,
+        (false, true, true, true) => symbols.horizontal_down,
+        (false, true, false, true) => symbols.top_left,
+        (false, true, true, false) => symbols.top_right,
+        (true, false, false, true) => symbols.bottom_left,
+        (true, false, true, false) => symbols.bottom_right,
+        (_, _, false, false) => symbols.vertical,
+        _ => symbols.horizontal,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ratatui::{buffer::Buffer, layout::Rect, style::Modifier, widgets::Widget};
+
+    use crate::tuihtml::{parser::construct_widget, theme::Theme, widget::HtmlWidget};
+
+    fn render(html: &str, theme: &Theme, width: u16, height: u16) -> (Vec<String>, Buffer) {
+        let (widget, _) = HtmlWidget::build(html, Some(theme), None);
+
+        let mut buffer = Buffer::empty(Rect::new(0, 0, width, height));
+        (&widget).render(buffer.area, &mut buffer);
+        let rows = (0..height).map(|y| (0..width).map(|x| buffer[(x, y)].symbol()).collect::<String>().trim_end().to_owned()).collect();
+        (rows, buffer)
+    }
+
+    #[test]
+    fn tables_are_drawn_with_borders_around_sized_columns() {
+        let html = concat!(
+            "<table><caption>Fruit</caption>",
+            "<tr><td rowspan=2>Apple</td><td>1.20</td><td>Crisp and sweet</td></tr>",
+            "<tr><td colspan=2>Seasonal</td></tr>",
+            // Header rows go first wherever they are.
+            "<thead><tr><th>Name<th>Price<th>Notes</thead>",
+            "</table><p>After</p>",
+        );
+
+        let (rows, buffer) = render(html, &Theme::default(), 40, 10);
+
+        assert_eq!(rows, vec![
+            "               Fruit",
+            "┌───────┬───────┬─────────────────┐",
+            "│ Name  │ Price │      Notes      │",
+            "├───────┼───────┼─────────────────┤",
+            "│ Apple │ 1.20  │ Crisp and sweet │",
+            "│       ├───────┴─────────────────┤",
+            "│       │ Seasonal                │",
+            "└───────┴─────────────────────────┘",
+            "",
+            "After",
+        ]);
+        assert!(buffer[(2, 2)].style().add_modifier.contains(Modifier::BOLD));
+        assert!(!buffer[(2, 4)].style().add_modifier.contains(Modifier::BOLD));
+
+        // Narrower, the widest column gives up room first and wraps.
+        let (rows, _) = render(html, &Theme::default(), 26, 10);
+
+        assert_eq!(rows, vec![
+            "          Fruit",
+            "┌───────┬───────┬────────┐",
+            "│ Name  │ Price │ Notes  │",
+            "├───────┼───────┼────────┤",
+            "│ Apple │ 1.20  │ Crisp  │",
+            "│       │       │ and    │",
+            "│       │       │ sweet  │",
+            "│       ├───────┴────────┤",
+            "│       │ Seasonal       │",
+            "└───────┴────────────────┘",
+        ]);
+    }
+
+    #[test]
+    fn wide_tables_are_cut_off_at_the_edge() {
+        let row = "<tr>".to_owned() + &"<td colspan=1000>cell</td>".repeat(10);
+        let html = format!("<table>{}</table>", row.repeat(100));
+
+        let widget = construct_widget(&html);
+        let lines = widget.tables[0].table.lines(20);
+
+        assert_eq!(widget.tables[0].table.columns, 1000);
+        assert!(lines.iter().all(|line| line.width() <= 20));
+        assert_eq!(lines.len(), 201);
+        assert_eq!(lines[1].to_string(), format!("\u{2502} cell{}", " ".repeat(14)));
+    }
+
+    #[test]
+    fn cells_outside_rows_make_rows_of_their_own() {
+        let theme = Theme { table_border: None, ..Theme::default() };
+
+        let (rows, _) = render("<table><td>a</td><td>b</td></table>", &theme, 20, 2);
+        assert_eq!(rows, vec!["a  b", ""]);
+
+        let (rows, _) = render("<table><tbody><td>x</td></tbody></table>", &theme, 20, 2);
+        assert_eq!(rows, vec!["x", ""]);
+
+        let (rows, _) = render("<table><td>a</td><tr><td>b</td><td>c</td></tr><td>d</td></table>", &theme, 20, 4);
+        assert_eq!(rows, vec!["a", "b  c", "d", ""]);
+    }
+
+    #[test]
+    fn content_outside_cells_goes_before_the_table() {
+        let theme = Theme { table_border: None, ..Theme::default() };
+
+        let (rows, _) = render("<table>oops<tr><td>a</td></tr></table>", &theme, 20, 3);
+        assert_eq!(rows, vec!["oops", "a", ""]);
+
+        let (rows, _) = render("<table><tr><td>a</td></tr><div>block</div></table>", &theme, 20, 3);
+        assert_eq!(rows, vec!["block", "a", ""]);
+
+        let (rows, _) = render("<p>x</p><table><tr>row text<td>a</td></tr></table>", &theme, 20, 4);
+        assert_eq!(rows, vec!["x", "", "row text", "a"]);
+    }
+
+    #[test]
+    fn large_tables_are_built_in_linear_time() {
+        // Placing each cell used to look through every cell before it, which
+        // took minutes for these.
+        let row = "<tr>".to_owned() + &"<td>x</td>".repeat(150);
+        let widget = construct_widget(&format!("<table>{}</table>", row.repeat(150)));
+        let table = &widget.tables[0].table;
+        assert_eq!((table.rows, table.columns, table.cells.len()), (150, 150, 150 * 150));
+
+        // Cells past the last column are dropped before they are laid out.
+        let widget = construct_widget(&format!("<table><tr>{}</table>", "<td>x</td>".repeat(5000)));
+        let table = &widget.tables[0].table;
+        assert_eq!((table.rows, table.columns, table.cells.len()), (1, 1000, 1000));
+    }
+
+    #[test]
+    fn borders_can_be_left_out() {
+        let html = "<table><tr><td>one</td><td>two words</td></tr><tr><td colspan=2>three</td></tr></table>";
+
+        let theme = Theme { table_border: None, ..Theme::default() };
+        let (rows, _) = render(html, &theme, 20, 3);
+        assert_eq!(rows, vec!["one  two words", "three", ""]);
+
+        // Not even the longest words fit: they are broken.
+        let (rows, _) = render(html, &theme, 8, 4);
+        assert_eq!(rows, vec!["one  two", "     wor", "     ds", "three"]);
+
+        let widget = construct_widget(html);
+        assert_eq!(widget.tables.len(), 1);
+        assert_eq!((widget.tables[0].line, widget.tables[0].lines), (0, 5));
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+
+use ratatui::{layout::Alignment, style::{Style, Stylize}, symbols::line, text::{Line, Span}};
+
+use crate::tuihtml::{color::Palette, html::{HtmlElement, HtmlTag}};
+
+// How wide a full width rule or a table is drawn before the area it goes
+// in is known.
+pub(crate) const FALLBACK_WIDTH: usize = 120;
+
+// How elements are drawn: the style of their text, what goes around it and,
+// for block elements, how their lines are laid out. Elements are looked up
+// by tag, then by each of their classes and then by id, with every match
+// patched over the ones before it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    pub tags: HashMap<HtmlTag, ElementStyle>,
+    pub classes: HashMap<String, ElementStyle>,
+    pub ids: HashMap<String, ElementStyle>,
+    // Style of the [n] after each link, None to leave the numbers out.
+    pub link_marker: Option<Style>,
+    // How every color, the page's and the theme's, is fitted to the
+    // terminal.
+    pub palette: Palette,
+    // None to draw tables without borders.
+    pub table_border: Option<TableBorder>,
+    // How a quote's source is shown, taken from its cite attribute or a
+    // <cite> at its end. None to leave the cite attribute out.
+    pub attribution: Option<ElementStyle>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ElementStyle {
+    // Patched onto the text inside the element.
+    pub style: Style,
+    // Put before and after the element's text, in the element's style
+    // patched with their own.
+    pub prefix: Option<Span<'static>>,
+    pub suffix: Option<Span<'static>>,
+    // Set for elements whose text ends up on lines of its own.
+    pub block: Option<BlockStyle>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BlockStyle {
+    pub alignment: Option<Alignment>,
+    // Patched onto the whole line, where `style` only covers the text.
+    // Lines of text between the blocks inside this one get it too.
+    pub line_style: Style,
+    // Blank lines before and after the block. Margins next to each other
+    // collapse into the larger of them.
+    pub margin_top: usize,
+    pub margin_bottom: usize,
+    // Blank lines inside the block, in its line style. These never
+    // collapse.
+    pub padding_top: usize,
+    pub padding_bottom: usize,
+    // Columns the block, and every block inside it, is moved right by.
+    pub indent: usize,
+    // Drawn at the start of the indent on every line inside the block, like
+    // the bar down the side of a quote.
+    pub gutter: Option<Span<'static>>,
+    pub rule_above: Option<Rule>,
+    pub rule_below: Option<Rule>,
+}
+
+// A line drawn with one repeated character, like the overscore under an H1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rule {
+    pub symbol: char,
+    pub width: RuleWidth,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleWidth {
+    Fixed(usize),
+    // This many times the width of the block's text.
+    Text(usize),
+    // As wide as the area the widget is rendered in.
+    Full,
+}
+
+// How the lines around and between table cells are drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TableBorder {
+    pub symbols: line::Set,
+    pub style: Style,
+}
+
+impl Default for TableBorder {
+    fn default() -> Self {
+        TableBorder { symbols: line::NORMAL, style: Style::default() }
+    }
+}
+
+// A rule among the lines of a layout, kept so it can be drawn again to fit
+// the area the lines end up in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RuleLine {
+    pub(crate) line: usize,
+    pub(crate) rule: Rule,
+    // The width of the text of the block the rule belongs to.
+    pub(crate) text_width: usize,
+}
+
+// The default look in true color, the same whatever the environment says.
+// `Theme::for_terminal` is this look with colors the terminal can show.
+impl Default for Theme {
+    fn default() -> Self {
+        let bold = ElementStyle::from(Style::new().bold());
+        let block = |block: BlockStyle| ElementStyle { block: Some(block), ..ElementStyle::default() };
+        let heading = |block: BlockStyle| ElementStyle { style: Style::new().bold(), block: Some(block), ..ElementStyle::default() };
+        let quote = || block(BlockStyle { margin_top: 1, margin_bottom: 1, indent: 2, ..BlockStyle::default() });
+        let blockquote = ElementStyle {
+            style: Style::new().italic(),
+            block: Some(BlockStyle {
+                gutter: Some(Span::styled("\u{2502}", Style::new().dark_gray())),
+                ..quote().block.unwrap_or_default()
+            }),
+            ..ElementStyle::default()
+        };
+
+        let tags = HashMap::from([
+            (HtmlTag::BOLD, bold.clone()),
+            (HtmlTag::STRONG, bold),
+            (HtmlTag::EM, Style::new().italic().into()),
+            (HtmlTag::I, Style::new().italic().into()),
+            (HtmlTag::U, Style::new().underlined().into()),
+            (HtmlTag::A, Style::new().underlined().into()),
+            (HtmlTag::S, Style::new().crossed_out().into()),
+            (HtmlTag::DEL, Style::new().crossed_out().into()),
+            (HtmlTag::H1, heading(BlockStyle {
+                alignment: Some(Alignment::Center),
+                rule_below: Some(Rule { symbol: '\u{00AF}', width: RuleWidth::Text(2) }),
+                ..BlockStyle::default()
+            })),
+            (HtmlTag::H2, heading(BlockStyle { margin_top: 1, line_style: Style::new().underlined(), ..BlockStyle::default() })),
+            (HtmlTag::H3, heading(BlockStyle { margin_top: 1, ..BlockStyle::default() })),
+            (HtmlTag::H4, heading(BlockStyle { margin_top: 1, ..BlockStyle::default() })),
+            (HtmlTag::H5, heading(BlockStyle { margin_top: 1, ..BlockStyle::default() })),
+            (HtmlTag::H6, heading(BlockStyle { margin_top: 1, ..BlockStyle::default() })),
+            (HtmlTag::HR, block(BlockStyle {
+                alignment: Some(Alignment::Center),
+                rule_above: Some(Rule { symbol: '\u{005F}', width: RuleWidth::Full }),
+                rule_below: Some(Rule { symbol: '\u{00AF}', width: RuleWidth::Full }),
+                ..BlockStyle::default()
+            })),
+            (HtmlTag::P, block(BlockStyle { margin_bottom: 1, ..BlockStyle::default() })),
+            (HtmlTag::OL, block(BlockStyle { margin_bottom: 1, indent: 2, ..BlockStyle::default() })),
+            (HtmlTag::UL, block(BlockStyle { margin_bottom: 1, indent: 2, ..BlockStyle::default() })),
+            (HtmlTag::LI, block(BlockStyle::default())),
+            (HtmlTag::BLOCKQUOTE, blockquote),
+            (HtmlTag::FIGURE, quote()),
+            (HtmlTag::ASIDE, block(BlockStyle { indent: 2, ..BlockStyle::default() })),
+            (HtmlTag::DIV, block(BlockStyle::default())),
+            (HtmlTag::NAV, block(BlockStyle::default())),
+            (HtmlTag::HEADER, block(BlockStyle::default())),
+            (HtmlTag::FOOTER, block(BlockStyle::default())),
+            (HtmlTag::MAIN, block(BlockStyle::default())),
+            (HtmlTag::SECTION, block(BlockStyle::default())),
+            (HtmlTag::ARTICLE, block(BlockStyle::default())),
+            (HtmlTag::FIGCAPTION, block(BlockStyle::default())),
+            (HtmlTag::TABLE, block(BlockStyle { margin_bottom: 1, ..BlockStyle::default() })),
+            (HtmlTag::CAPTION, block(BlockStyle { alignment: Some(Alignment::Center), ..BlockStyle::default() })),
+            (HtmlTag::THEAD, Style::new().bold().into()),
+            (HtmlTag::TH, heading(BlockStyle { alignment: Some(Alignment::Center), ..BlockStyle::default() })),
+        ]);
+
+        Theme {
+            tags,
+            classes: HashMap::new(),
+            ids: HashMap::new(),
+            link_marker: Some(Style::new().bold()),
+            palette: Palette::default(),
+            table_border: Some(TableBorder::default()),
+            attribution: Some(ElementStyle {
+                style: Style::new().not_italic(),
+                prefix: Some(Span::raw("\u{2014} ")),
+                block: Some(BlockStyle::default()),
+                ..ElementStyle::default()
+            }),
+        }
+    }
+}
+
+impl Theme {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // No styling at all: every element is inline text.
+    pub fn plain() -> Self {
+        Theme {
+            tags: HashMap::new(),
+            classes: HashMap::new(),
+            ids: HashMap::new(),
+            link_marker: None,
+            palette: Palette::default(),
+            table_border: None,
+            attribution: None,
+        }
+    }
+
+    // The default look, with colors fitted to what the environment says
+    // about the terminal. This turns colors off when NO_COLOR is set.
+    pub fn for_terminal() -> Self {
+        Theme { palette: Palette::detect(), ..Theme::default() }
+    }
+
+    pub fn set_tag(&mut self, tag: HtmlTag, style: ElementStyle) {
+        self.tags.insert(tag, style);
+    }
+
+    pub fn set_class(&mut self, class: &str, style: ElementStyle) {
+        self.classes.insert(class.to_owned(), style);
+    }
+
+    pub fn set_id(&mut self, id: &str, style: ElementStyle) {
+        self.ids.insert(id.to_owned(), style);
+    }
+
+    pub fn resolve(&self, element: &HtmlElement) -> ElementStyle {
+        let mut resolved = self.tags.get(&element.tag).cloned().unwrap_or_default();
+
+        for class in element.classes() {
+            if let Some(style) = self.classes.get(class) {
+                resolved.patch(style);
+            }
+        }
+        if let Some(style) = element.id().and_then(|id| self.ids.get(id)) {
+            resolved.patch(style);
+        }
+
+        resolved
+    }
+}
+
+impl ElementStyle {
+    // Lays `other` over this style. Its decorations and block replace these
+    // where it has them.
+    pub fn patch(&mut self, other: &ElementStyle) {
+        self.style = self.style.patch(other.style);
+        if other.prefix.is_some() {
+            self.prefix = other.prefix.clone();
+        }
+        if other.suffix.is_some() {
+            self.suffix = other.suffix.clone();
+        }
+        if other.block.is_some() {
+            self.block = other.block.clone();
+        }
+    }
+}
+
+impl From<Style> for ElementStyle {
+    fn from(style: Style) -> Self {
+        ElementStyle { style, ..ElementStyle::default() }
+    }
+}
+
+impl BlockStyle {
+    // The lines of a block whose text is `spans`, and which of them are
+    // rules. Margins, padding and indents are left to the layout, which
+    // knows the blocks around this one.
+    pub(crate) fn lines<'a>(&self, spans: Vec<Span<'a>>) -> (Vec<Line<'a>>, Vec<RuleLine>) {
+        let text_width: usize = spans.iter().map(Span::width).sum();
+        let mut lines = Vec::new();
+        let mut rules = Vec::new();
+
+        let mut push_rule = |lines: &mut Vec<Line<'a>>, rule: Option<Rule>| {
+            if let Some((rule, line)) = rule.and_then(|rule| Some((rule, rule.line(text_width)?))) {
+                rules.push(RuleLine { line: lines.len(), rule, text_width });
+                lines.push(self.align(line));
+            }
+        };
+
+        push_rule(&mut lines, self.rule_above);
+        if !spans.is_empty() {
+            lines.push(self.align(Line::from(spans).style(self.line_style)));
+        }
+        push_rule(&mut lines, self.rule_below);
+
+        (lines, rules)
+    }
+
+    fn align<'a>(&self, line: Line<'a>) -> Line<'a> {
+        match self.alignment {
+            Some(alignment) => line.alignment(alignment),
+            None => line,
+        }
+    }
+}
+
+impl Rule {
+    // How wide the rule is for a block whose text is `text_width` columns
+    // wide, in an area `area_width` columns wide once that is known. It is
+    // never wider than the area.
+    pub(crate) fn width(&self, text_width: usize, area_width: Option<usize>) -> usize {
+        let width = match self.width {
+            RuleWidth::Fixed(width) => width,
+            RuleWidth::Text(scale) => text_width * scale,
+            RuleWidth::Full => area_width.unwrap_or(FALLBACK_WIDTH),
+        };
+        area_width.map_or(width, |area_width| width.min(area_width))
+    }
+
+    // The rule for a block whose text is `text_width` columns wide, before
+    // the area is known. None if it would be empty.
+    pub(crate) fn line<'a>(&self, text_width: usize) -> Option<Line<'a>> {
+        match self.width(text_width, None) {
+            0 => None,
+            width => Some(Line::from(self.symbol.to_string().repeat(width))),
+        }
+    }
+}
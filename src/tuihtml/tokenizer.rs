@@ -1,12 +1,15 @@
-use std::{collections::HashMap, str::Chars};
+use std::{io::{self, Read}, iter::FusedIterator, str::Chars};
 
-use crate::tuihtml::html::{HtmlElement, HtmlTag};
+use crate::tuihtml::{diagnostics::{Diagnostics, ParseError, ParseErrorKind}, entities::decode_entities, input::HtmlInput, html::{Attributes, HtmlElement, HtmlTag}, source::{SourcePosition, SourceSpan}};
 
 // Enum for the different HTML tags
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Token {
     Element(HtmlElement),
     Text(String),
+    Comment(String),
+    Doctype(String),
+    CData(String),
     NewLine,
     Eof,
 }
@@ -16,6 +19,9 @@ impl Token {
         match token {
             Token::Element(e) => Some(e.clone()),
             Token::Text(_) => None,
+            Token::Comment(_) => None,
+            Token::Doctype(_) => None,
+            Token::CData(_) => None,
             Token::NewLine => None,
             Token::Eof => None,
         }
@@ -25,6 +31,9 @@ impl Token {
         match token {
             Token::Element(_) => None,
             Token::Text(s) => Some(s.clone()),
+            Token::Comment(_) => None,
+            Token::Doctype(_) => None,
+            Token::CData(s) => Some(s.clone()),
             Token::NewLine => None,
             Token::Eof => None,
         }
@@ -45,49 +54,200 @@ impl Token {
     }
 }
 
-#[derive(Debug, Clone)]
+// Tokenizes `html`, yielding tokens up to but not including Token::Eof.
+pub fn tokenize(html: &str) -> HtmlTokenizer<'_> {
+    HtmlTokenizer::new(html.chars())
+}
+
+// Elements whose content is not markup. Everything up to the matching end
+// tag is a single text token; RCDATA (`decode` set) still decodes character
+// references, RAWTEXT and script data do not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RawText {
+    end_tag: &'static str,
+    decode: bool,
+}
+
+fn raw_text(tag: &HtmlTag) -> Option<RawText> {
+    match tag {
+        HtmlTag::SCRIPT => Some(RawText { end_tag: "script", decode: false }),
+        HtmlTag::STYLE => Some(RawText { end_tag: "style", decode: false }),
+        HtmlTag::IFRAME => Some(RawText { end_tag: "iframe", decode: false }),
+        HtmlTag::TEXTAREA => Some(RawText { end_tag: "textarea", decode: true }),
+        HtmlTag::TITLE => Some(RawText { end_tag: "title", decode: true }),
+        _ => None,
+    }
+}
+
+// Where a tokenizer stopped, so another one can carry on from there over
+// the rest of the input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct TokenizerState {
+    pub(crate) position: SourcePosition,
+    raw_text: Option<RawText>,
+}
+
+impl TokenizerState {
+    pub(crate) fn in_raw_text(&self) -> bool {
+        self.raw_text.is_some()
+    }
+}
+
+#[derive(Debug)]
 pub struct HtmlTokenizer<'a> {
-    html_pos: Chars<'a>,
+    html_pos: HtmlInput<'a>,
     next_char: Option<char>,
+    raw_text: Option<RawText>,
+    // Where `next_char` sits in the source.
+    position: SourcePosition,
+    // Set when a token ends before whitespace that was consumed with it.
+    token_end: Option<SourcePosition>,
+    // Where the token being captured started, for error spans.
+    token_start: SourcePosition,
+    diagnostics: Diagnostics,
+    max_attributes: Option<usize>,
+    attributes_dropped: bool,
 }
 
 impl<'a> HtmlTokenizer<'a> {
 
     pub fn new(html: Chars<'a>) -> HtmlTokenizer<'a> {
-        let mut tokenizer = HtmlTokenizer {
-            html_pos: html,
-            next_char: None,
-        };
+        HtmlTokenizer::from_input(HtmlInput::from_chars(html))
+    }
+
+    // Tokenizes UTF-8 read from `reader` as tokens are asked for, so the
+    // whole document never has to be in memory at once.
+    pub fn from_reader(reader: impl Read + 'a) -> HtmlTokenizer<'a> {
+        HtmlTokenizer::from_input(HtmlInput::from_reader(reader))
+    }
 
+    // Continues tokenizing at `state` over `html`, the input that follows
+    // it. Unlike new(), leading whitespace is kept.
+    pub(crate) fn resume(html: Chars<'a>, state: TokenizerState) -> HtmlTokenizer<'a> {
+        let mut tokenizer = HtmlTokenizer::with_state(HtmlInput::from_chars(html), state);
+        tokenizer.next_char = tokenizer.html_pos.next();
+        tokenizer
+    }
+
+    pub(crate) fn state(&self) -> TokenizerState {
+        TokenizerState {
+            position: self.position,
+            raw_text: self.raw_text,
+        }
+    }
+
+    // Whether tokenizing has looked at the end of the input, meaning the
+    // last token may have turned out differently with more input after it.
+    pub(crate) fn is_exhausted(&self) -> bool {
+        self.html_pos.is_exhausted()
+    }
+
+    fn from_input(html: HtmlInput<'a>) -> HtmlTokenizer<'a> {
+        let mut tokenizer = HtmlTokenizer::with_state(html, TokenizerState::default());
         tokenizer.init();
         tokenizer
     }
 
+    fn with_state(html: HtmlInput<'a>, state: TokenizerState) -> HtmlTokenizer<'a> {
+        HtmlTokenizer {
+            html_pos: html,
+            next_char: None,
+            raw_text: state.raw_text,
+            position: state.position,
+            token_end: None,
+            token_start: SourcePosition::default(),
+            diagnostics: Diagnostics::default(),
+            max_attributes: None,
+            attributes_dropped: false,
+        }
+    }
+
+    // Parse errors are only collected once this is called; by default they
+    // are recovered from silently.
+    pub fn enable_diagnostics(&mut self) {
+        self.diagnostics = Diagnostics::new(true);
+    }
+
+    pub fn take_errors(&mut self) -> Vec<ParseError> {
+        self.diagnostics.take()
+    }
+
+    // Attributes past the first `max_attributes` of an element are dropped.
+    pub fn set_max_attributes(&mut self, max_attributes: usize) {
+        self.max_attributes = Some(max_attributes);
+    }
+
+    pub fn attributes_dropped(&self) -> bool {
+        self.attributes_dropped
+    }
+
+    // The error that cut a reader's input short, if any. The tokens before
+    // it are still valid, the last of them being Token::Eof.
+    pub fn take_io_error(&mut self) -> Option<io::Error> {
+        self.html_pos.take_error()
+    }
+
     pub fn init(&mut self) {
         self.next_char = self.html_pos.next();
         self.consume_whitespace();
     }
 
-    pub fn next(&mut self) -> Token  {
+    // Returns the next token and where in the source it came from. Unlike
+    // next(), this returns Token::Eof at the end, and keeps returning it.
+    pub fn next_with_span(&mut self) -> (Token, SourceSpan) {
+        let start = self.position;
+        self.token_start = start;
+        let token = self.next_token();
+        let end = self.token_end.take().unwrap_or(self.position);
+
+        (token, SourceSpan::new(start, end))
+    }
+
+    fn next_token(&mut self) -> Token {
+        if let Some(raw_text) = self.raw_text.take() {
+            let text = self.capture_raw_text(raw_text);
+            if !text.is_empty() {
+                return Token::Text(text);
+            }
+        }
+
         let mut lexeme = String::new();
 
         loop {
             match self.next_char {
+                // A '<' that cannot start markup, as in "a < b", is text.
+                Some('<') if !self.html_pos.peek(0).is_some_and(|c| c.is_ascii_alphabetic() || matches!(c, '/' | '!' | '?')) => {
+                    lexeme.push('<');
+                    self.next_char();
+                },
                 Some('<') => {
                     if lexeme.is_empty() {
                         self.next_char();
-                        return Token::Element(self.capture_element())
+                        return match self.next_char {
+                            Some('!') => {
+                                self.next_char();
+                                self.capture_markup_declaration()
+                            },
+                            Some('?') => Token::Comment(self.capture_bogus_comment()),
+                            _ => {
+                                let element = self.capture_element();
+                                if !element.closing {
+                                    self.raw_text = raw_text(&element.tag);
+                                }
+                                Token::Element(element)
+                            },
+                        };
                     }
                     // Leave '<' unconsumed as next_char so the next call
                     // to next() can begin parsing the element.
-                    return Token::Text(lexeme);
+                    return Token::Text(decode_entities(&lexeme, false));
                 },
                 Some('\n') | Some('\r') => {
                     if lexeme.is_empty() {
                         self.consume_whitespace();
                         return Token::NewLine;
                     }
-                    return Token::Text(lexeme);
+                    return Token::Text(decode_entities(&lexeme, false));
                 },
                 None => {
                     break;
@@ -105,121 +265,325 @@ impl<'a> HtmlTokenizer<'a> {
             Token::Eof
         }
         else {
-            Token::Text(lexeme)
+            Token::Text(decode_entities(&lexeme, false))
         }
     }
 
     fn capture_element(&mut self) -> HtmlElement {
         let mut closing_tag = false;
         let mut tag = String::new();
-        let mut attributes = HashMap::new();
 
-        loop {
+        if self.next_char == Some('/') {
+            closing_tag = true;
+            self.next_char();
+        }
 
-            if self.next_char.is_some_and(|c| c.is_whitespace()) && !closing_tag {
-                self.consume_whitespace();
-                attributes = self.capture_tag_attributes();
+        while let Some(char) = self.next_char {
+            if char.is_whitespace() || char == '/' || char == '>' {
+                break;
             }
+            tag.push(char);
+            self.next_char();
+        }
 
-            match self.next_char {
-                Some('/') => {
-                    closing_tag = true;
-                    self.next_char();
-                },
-                Some('>') => {
-                    self.next_char();
-                    // Only collapse trailing newlines/whitespace after '>'.
-                    // In a TUI context we may not know what styling applies,
-                    // so we trim for clarity rather than preserving all whitespace.
-                    if self.next_char.is_some_and(|c| c == '\n' || c == '\r' ) {
-                        self.consume_whitespace();
-                    }
-                    return HtmlElement::new(HtmlTag::from_string(&tag), attributes, closing_tag);
-                },
-                None => {
-                    break;
-                }
-                _ => {}
+        // Sloppy markup sometimes puts attributes on end tags; they are
+        // parsed so they don't leak into the text, then dropped.
+        let mut attributes = self.capture_tag_attributes();
+        if closing_tag && !attributes.is_empty() {
+            self.report(ParseErrorKind::EndTagWithAttributes, || format!("end tag </{}> has attributes", tag));
+            attributes.clear();
+        }
+
+        // capture_tag_attributes only stops at a '/' that is followed by
+        // '>', which marks a self-closing start tag like `<br/>`.
+        let mut self_closing = false;
+        if self.next_char == Some('/') {
+            self.next_char();
+            self_closing = !closing_tag;
+        }
+
+        match self.next_char == Some('>') {
+            true => {
+                self.next_char();
+                self.consume_trailing_newline();
+            },
+            false => self.report(ParseErrorKind::EofInTag, || format!("unexpected end of input in <{}> tag", tag)),
+        }
+
+        HtmlElement::new(HtmlTag::from_string(&tag), attributes, closing_tag, self_closing)
+    }
+
+    // Reads up to, but not including, the end tag that closes the raw text
+    // element, so the next call to next() tokenizes the end tag normally.
+    fn capture_raw_text(&mut self, raw_text: RawText) -> String {
+        let mut text = String::new();
+
+        while let Some(char) = self.next_char {
+            if char == '<' && self.at_end_tag(raw_text.end_tag) {
+                break;
+            }
+            text.push(char);
+            self.next_char();
+        }
+
+        match raw_text.decode {
+            true => decode_entities(&text, false),
+            false => text,
+        }
+    }
+
+    // Whether the input after the current '<' is `/name` followed by
+    // whitespace, '/' or '>'. Matching is case-insensitive.
+    fn at_end_tag(&mut self, name: &str) -> bool {
+        if self.html_pos.peek(0) != Some('/') {
+            return false;
+        }
+
+        for (i, expected) in name.chars().enumerate() {
+            if !self.html_pos.peek(i + 1).is_some_and(|c| c.eq_ignore_ascii_case(&expected)) {
+                return false;
+            }
+        }
+
+        self.html_pos.peek(name.chars().count() + 1).is_none_or(|c| c.is_whitespace() || c == '/' || c == '>')
+    }
+
+    // Called just after "<!". Picks between a comment, DOCTYPE and CDATA
+    // section; anything else is a bogus comment running to the next '>'.
+    fn capture_markup_declaration(&mut self) -> Token {
+        if self.consume_prefix("--", false) {
+            return Token::Comment(self.capture_comment());
+        }
+
+        if self.consume_prefix("doctype", true) {
+            let (doctype, closed) = self.capture_until(">");
+            if !closed {
+                self.report(ParseErrorKind::EofInDoctype, || "unexpected end of input in DOCTYPE".to_string());
             }
+            self.consume_trailing_newline();
+            return Token::Doctype(doctype.trim().to_string());
+        }
 
-            if let Some(char) = self.next_char {
-                tag.push(char);
+        if self.consume_prefix("[CDATA[", false) {
+            let (data, closed) = self.capture_until("]]>");
+            if !closed {
+                self.report(ParseErrorKind::EofInCData, || "unexpected end of input in CDATA section".to_string());
             }
+            self.consume_trailing_newline();
+            return Token::CData(data);
+        }
+
+        Token::Comment(self.capture_bogus_comment())
+    }
+
+    // Called just after "<!--". A '>' inside the comment does not end it;
+    // only "-->" (or the tolerated "--!>") does, and "<!-->" and "<!--->"
+    // are empty comments.
+    fn capture_comment(&mut self) -> String {
+        if self.consume_prefix(">", false) || self.consume_prefix("->", false) {
+            self.consume_trailing_newline();
+            return String::new();
+        }
+
+        let mut comment = String::new();
+        let mut closed = false;
+
+        while let Some(char) = self.next_char {
+            comment.push(char);
             self.next_char();
+
+            if comment.ends_with("-->") {
+                comment.truncate(comment.len() - 3);
+                closed = true;
+                break;
+            }
+            if comment.ends_with("--!>") {
+                comment.truncate(comment.len() - 4);
+                closed = true;
+                break;
+            }
         }
 
-        HtmlElement::new(HtmlTag::from_string(&tag), attributes, closing_tag)
+        if !closed {
+            self.report(ParseErrorKind::EofInComment, || "unexpected end of input in comment".to_string());
+        }
 
+        self.consume_trailing_newline();
+        comment
     }
 
-    fn capture_tag_attributes(&mut self) -> HashMap<String, String> {
-        let mut attributes = HashMap::new();
-        let mut key = String::new();
-        let mut value = String::new();
+    // Processing instructions like `<?xml ... ?>` and unknown `<!...>`
+    // declarations are kept as comments, the way browsers treat them.
+    fn capture_bogus_comment(&mut self) -> String {
+        let (comment, closed) = self.capture_until(">");
+        if !closed {
+            self.report(ParseErrorKind::EofInComment, || "unexpected end of input in comment".to_string());
+        }
+        self.consume_trailing_newline();
+        comment
+    }
+
+    // Consumes input up to and including `terminator`, returning everything
+    // before it and whether the terminator was found. Runs to the end of the
+    // input if the terminator never shows.
+    fn capture_until(&mut self, terminator: &str) -> (String, bool) {
+        let mut captured = String::new();
 
-        #[derive(PartialEq)]
-        enum AttributeState {
-            Key,
-            Value
+        while let Some(char) = self.next_char {
+            captured.push(char);
+            self.next_char();
+
+            if captured.ends_with(terminator) {
+                captured.truncate(captured.len() - terminator.len());
+                return (captured, true);
+            }
         }
 
-        let mut state = AttributeState::Key;
+        (captured, false)
+    }
+
+    // Consumes `prefix` if the input continues with it, leaving the input
+    // untouched otherwise.
+    fn consume_prefix(&mut self, prefix: &str, ignore_case: bool) -> bool {
+        let mut matched = 0;
+        for expected in prefix.chars() {
+            let actual = match matched {
+                0 => self.next_char,
+                n => self.html_pos.peek(n - 1),
+            };
+            let matches = actual.is_some_and(|actual| match ignore_case {
+                true => expected.eq_ignore_ascii_case(&actual),
+                false => expected == actual,
+            });
+            if !matches {
+                break;
+            }
+            matched += 1;
+        }
+
+        if matched != prefix.chars().count() {
+            return false;
+        }
+
+        for _ in 0..matched {
+            self.next_char();
+        }
+        true
+    }
+
+    fn capture_tag_attributes(&mut self) -> Attributes {
+        let mut attributes = Attributes::new();
+
+        loop {
+            self.consume_whitespace();
 
-        while self.next_char.is_some() {
             match self.next_char {
-                Some('>') => {
-                    return attributes;
-                },
-                Some('=') => {
-                    if state == AttributeState::Key {
-                        state = AttributeState::Value;
-                        self.next_char();           // advance past '='
-                        self.consume_whitespace();
-                        self.next_char();           // skip the opening quote so value content is
-                                                    // captured directly; the closing quote will be
-                                                    // caught by the quote arm below
+                None | Some('>') => break,
+                Some('/') => {
+                    // Leave the '/' of a trailing "/>" for capture_element;
+                    // a stray '/' between attributes is skipped.
+                    if self.html_pos.peek(0) == Some('>') {
+                        break;
                     }
+                    self.next_char();
                 },
-                Some('\"') | Some('\'') => {
-                    if state == AttributeState::Value {
-                        state = AttributeState::Key;
-                        attributes.insert(key.clone(), value.clone());
-                        self.next_char();
-                        self.consume_whitespace();
-                        key = String::new();
-                        value = String::new();
+                _ => {
+                    let (name, value) = self.capture_attribute();
+                    let full = self.max_attributes.is_some_and(|max| attributes.len() >= max);
+                    if full && !attributes.contains(&name) {
+                        self.attributes_dropped = true;
+                    } else if full || !attributes.insert(name.clone(), value) {
+                        self.report(ParseErrorKind::DuplicateAttribute, || format!("duplicate attribute \"{}\" ignored", name));
                     }
                 }
-                None => {
-                    break;
+            }
+        }
+
+        attributes
+    }
+
+    // Reads one attribute, which may be `name`, `name=value`, `name='value'`
+    // or `name="value"`, with optional whitespace around the '='. Valueless
+    // attributes like `disabled` get an empty value.
+    fn capture_attribute(&mut self) -> (String, String) {
+        let mut name = String::new();
+
+        // A leading '=' belongs to the name rather than starting a value.
+        if self.next_char == Some('=') {
+            name.push('=');
+            self.next_char();
+        }
+
+        while let Some(char) = self.next_char {
+            if char.is_whitespace() || matches!(char, '/' | '>' | '=') {
+                break;
+            }
+            name.push(char.to_ascii_lowercase());
+            self.next_char();
+        }
+
+        self.consume_whitespace();
+        if self.next_char != Some('=') {
+            return (name, String::new());
+        }
+        self.next_char();
+        self.consume_whitespace();
+
+        let mut value = String::new();
+
+        match self.next_char {
+            Some(quote) if quote == '"' || quote == '\'' => {
+                self.next_char();
+                let mut closed = false;
+                while let Some(char) = self.next_char {
+                    self.next_char();
+                    if char == quote {
+                        closed = true;
+                        break;
+                    }
+                    value.push(char);
                 }
-                _ => {
-                    match state {
-                        AttributeState::Key => {
-                            if self.next_char.is_some_and(|c| !c.is_whitespace()) {
-                                if let Some(char) = self.next_char {
-                                    key.push(char);
-                                }
-                            }
-                        },
-                        AttributeState::Value => {
-                            if let Some(char) = self.next_char {
-                                value.push(char);
-                            }
-                        }
+                if !closed {
+                    self.report(ParseErrorKind::UnclosedQuote, || format!("unclosed quote in value of attribute \"{}\"", name));
+                }
+            },
+            _ => {
+                while let Some(char) = self.next_char {
+                    if char.is_whitespace() || char == '>' {
+                        break;
                     }
+                    value.push(char);
                     self.next_char();
                 }
             }
         }
 
-        attributes
+        (name, decode_entities(&value, true))
+    }
+
+    // Errors cover the token being captured so far.
+    fn report(&mut self, kind: ParseErrorKind, message: impl FnOnce() -> String) {
+        let span = SourceSpan::new(self.token_start, self.position);
+        self.diagnostics.report(kind, span, message);
     }
 
     fn next_char(&mut self) {
+        if let Some(char) = self.next_char {
+            self.position.advance(char);
+        }
         self.next_char = self.html_pos.next();
     }
 
+    // Only collapse trailing newlines/whitespace after '>'.
+    // In a TUI context we may not know what styling applies,
+    // so we trim for clarity rather than preserving all whitespace.
+    fn consume_trailing_newline(&mut self) {
+        self.token_end = Some(self.position);
+        if self.next_char.is_some_and(|c| c == '\n' || c == '\r' ) {
+            self.consume_whitespace();
+        }
+    }
+
     fn consume_whitespace(&mut self) {
         while self.next_char.is_some_and(|c| c.is_whitespace()) {
             self.next_char();
@@ -227,3 +591,15 @@ impl<'a> HtmlTokenizer<'a> {
     }
 }
 
+impl Iterator for HtmlTokenizer<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        match self.next_with_span().0 {
+            Token::Eof => None,
+            token => Some(token),
+        }
+    }
+}
+
+impl FusedIterator for HtmlTokenizer<'_> {}
@@ -0,0 +1,869 @@
+use crate::tuihtml::{diagnostics::{Diagnostics, ParseError, ParseErrorKind}, dom::{Document, NodeData, NodeEdge, NodeId}, html::{Attributes, HtmlElement, HtmlTag}, source::SourceSpan, tokenizer::Token};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FormattingEntry {
+    Marker,
+    Element(NodeId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    Default,
+    ListItem,
+    Table,
+}
+
+// Block containers whose end tag closes everything opened inside them, as
+// long as they are actually open.
+fn is_block_container(tag: &HtmlTag) -> bool {
+    matches!(tag,
+        HtmlTag::ARTICLE |
+        HtmlTag::ASIDE |
+        HtmlTag::BLOCKQUOTE |
+        HtmlTag::DETAILS |
+        HtmlTag::DIALOG |
+        HtmlTag::DIV |
+        HtmlTag::FIELDSET |
+        HtmlTag::FIGCAPTION |
+        HtmlTag::FIGURE |
+        HtmlTag::FOOTER |
+        HtmlTag::HEADER |
+        HtmlTag::MAIN |
+        HtmlTag::NAV |
+        HtmlTag::OL |
+        HtmlTag::PRE |
+        HtmlTag::SECTION |
+        HtmlTag::SUMMARY |
+        HtmlTag::UL
+    )
+}
+
+// Start tags that implicitly end an open <p>.
+fn closes_p(tag: &HtmlTag) -> bool {
+    is_block_container(tag) ||
+        tag.is_heading() ||
+        matches!(tag, HtmlTag::P | HtmlTag::LI | HtmlTag::TABLE | HtmlTag::HR)
+}
+
+// Elements that push a marker onto the list of active formatting elements so
+// formatting opened outside of them is not reconstructed inside.
+fn pushes_marker(tag: &HtmlTag) -> bool {
+    matches!(tag, HtmlTag::TD | HtmlTag::TH | HtmlTag::CAPTION | HtmlTag::TEMPLATE)
+}
+
+// Elements that browsers close without complaint when the input ends.
+fn has_optional_end_tag(tag: &HtmlTag) -> bool {
+    matches!(tag,
+        HtmlTag::HTML | HtmlTag::BODY | HtmlTag::P | HtmlTag::LI | HtmlTag::OPTGROUP |
+        HtmlTag::TD | HtmlTag::TH | HtmlTag::TR | HtmlTag::THEAD | HtmlTag::TBODY | HtmlTag::TFOOT
+    )
+}
+
+fn is_scope_boundary(tag: &HtmlTag, scope: Scope) -> bool {
+    match scope {
+        Scope::Default => matches!(tag,
+            HtmlTag::HTML | HtmlTag::TABLE | HtmlTag::TD | HtmlTag::TH | HtmlTag::CAPTION | HtmlTag::TEMPLATE),
+        Scope::ListItem => is_scope_boundary(tag, Scope::Default) || matches!(tag, HtmlTag::OL | HtmlTag::UL),
+        Scope::Table => matches!(tag, HtmlTag::HTML | HtmlTag::TABLE | HtmlTag::TEMPLATE),
+    }
+}
+
+// Builds a `Document` from tokens following the "in body" rules of the HTML5
+// tree construction algorithm, so sloppy markup ends up with the same shape a
+// browser would give it: `<p>one<p>two` yields sibling paragraphs, stray end
+// tags are dropped and misnested formatting is repaired by the adoption
+// agency algorithm.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TreeBuilder {
+    document: Document,
+    open_elements: Vec<NodeId>,
+    active_formatting: Vec<FormattingEntry>,
+    // Source span of the token being processed.
+    span: SourceSpan,
+    // The end tag being processed, so the element it closes can take the
+    // end tag's span.
+    closing_tag: Option<HtmlTag>,
+    diagnostics: Diagnostics,
+    max_depth: Option<usize>,
+    // Where an element was first left out for being nested too deep.
+    depth_exceeded: Option<SourceSpan>,
+    // The elements left out for being nested too deep, with how many
+    // elements were open then, so their end tags are ignored rather than
+    // closing the elements that were kept.
+    dropped: Vec<(HtmlTag, usize)>,
+}
+
+impl TreeBuilder {
+    pub(crate) fn new() -> Self {
+        TreeBuilder {
+            document: Document::new(),
+            open_elements: Vec::new(),
+            active_formatting: Vec::new(),
+            span: SourceSpan::default(),
+            closing_tag: None,
+            diagnostics: Diagnostics::default(),
+            max_depth: None,
+            depth_exceeded: None,
+            dropped: Vec::new(),
+        }
+    }
+
+    // Elements that would be nested deeper than `max_depth` are left out,
+    // with their content going into the deepest open element instead.
+    pub(crate) fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = Some(max_depth);
+    }
+
+    pub(crate) fn depth_exceeded(&self) -> Option<SourceSpan> {
+        self.depth_exceeded
+    }
+
+    // Parse errors are only collected once this is called; by default they
+    // are recovered from silently.
+    pub(crate) fn enable_diagnostics(&mut self) {
+        self.diagnostics = Diagnostics::new(true);
+    }
+
+    pub(crate) fn document(&self) -> &Document {
+        &self.document
+    }
+
+    // Elements still open at the end of the input are closed there. Errors
+    // for elements whose end tag may not be omitted are reported at their
+    // start tag and can be collected with `finish_with_errors`.
+    pub(crate) fn finish(self) -> Document {
+        self.finish_with_errors().0
+    }
+
+    pub(crate) fn finish_with_errors(mut self) -> (Document, Vec<ParseError>) {
+        for id in self.open_elements.clone() {
+            let Some(tag) = self.tag_of(id).cloned() else {
+                continue;
+            };
+            if !has_optional_end_tag(&tag) {
+                let span = self.document.span(id);
+                self.diagnostics.report(ParseErrorKind::UnclosedElement, span, || format!("<{}> is never closed", tag.name()));
+            }
+        }
+
+        self.span.start = self.span.end;
+        while self.pop().is_some() {}
+
+        let root = self.document.root();
+        self.document.set_span_end(root, self.span.end);
+        (self.document, self.diagnostics.take())
+    }
+
+    // The last edge of a walk over the document up to which no later token
+    // can change the tree. Closed subtrees are done, except that the
+    // adoption agency may move content around inside an open formatting
+    // element, so the settled part stops short of the first of those.
+    pub(crate) fn settled_edge(&self) -> NodeEdge {
+        let mut parent = self.document.root();
+        let mut edge = NodeEdge::Start(parent);
+
+        for id in &self.open_elements {
+            if self.document.parent(*id) != Some(parent) || self.document.last_child(parent) != Some(*id) {
+                return edge;
+            }
+            if let Some(prev) = self.document.prev_sibling(*id) {
+                edge = NodeEdge::End(prev);
+            }
+            if self.formatting_position(*id).is_some() {
+                return edge;
+            }
+
+            parent = *id;
+            edge = NodeEdge::Start(parent);
+        }
+
+        // Trailing text in the current node can still grow.
+        let mut last = self.document.last_child(parent);
+        if last.is_some_and(|id| self.document.node(id).text().is_some()) {
+            last = last.and_then(|id| self.document.prev_sibling(id));
+        }
+
+        match last {
+            Some(id) => NodeEdge::End(id),
+            None => edge,
+        }
+    }
+
+    pub(crate) fn process(&mut self, token: Token, span: SourceSpan) {
+        self.span = span;
+
+        match token {
+            Token::Element(element) => {
+                match element.closing {
+                    true => {
+                        self.closing_tag = Some(element.tag.clone());
+                        self.end_tag(element.tag);
+                        self.closing_tag = None;
+                    },
+                    false => self.start_tag(element),
+                }
+            },
+            Token::Text(text) => {
+                self.reconstruct_active_formatting();
+                let parent = self.current_node();
+                self.document.append_text(parent, &text, span);
+            },
+            // CDATA is only meaningful inside <svg> and <math>, where it is
+            // plain character data. Anywhere else it is a bogus comment.
+            Token::CData(text) => {
+                let parent = self.current_node();
+                match self.has_open(&HtmlTag::SVG) || self.has_open(&HtmlTag::MATH) {
+                    true => {
+                        self.document.append_text(parent, &text, span);
+                    },
+                    false => {
+                        self.document.append(parent, NodeData::Comment(format!("[CDATA[{}]]", text)), span);
+                    },
+                }
+            },
+            Token::Comment(comment) => {
+                let parent = self.current_node();
+                self.document.append(parent, NodeData::Comment(comment), span);
+            },
+            Token::Doctype(doctype) => {
+                if self.document.doctype().is_none() {
+                    self.document.set_doctype(doctype);
+                }
+            },
+            // Source line breaks are kept in the text so the renderer can
+            // still break lines where the document did.
+            Token::NewLine => {
+                let parent = self.current_node();
+                self.document.append_text(parent, "\n", span);
+            },
+            Token::Eof => {}
+        }
+    }
+
+    fn start_tag(&mut self, element: HtmlElement) {
+        let tag = element.tag.clone();
+
+        match &tag {
+            HtmlTag::HTML | HtmlTag::BODY if self.has_open(&tag) => {}
+            tag if closes_p(tag) => {
+                if self.has_in_scope(|t| *t == HtmlTag::P, Scope::Default) {
+                    self.close_p();
+                }
+
+                if tag.is_heading() && self.current_tag().is_some_and(|t| t.is_heading()) {
+                    self.pop();
+                }
+
+                if *tag == HtmlTag::LI {
+                    self.close_list_item();
+                }
+
+                self.insert_element(element);
+            },
+            HtmlTag::A => {
+                if let Some(a) = self.formatting_after_marker(&HtmlTag::A) {
+                    self.report(ParseErrorKind::MisnestedTag, || "<a> inside another <a>".to_string());
+                    self.adoption_agency(&HtmlTag::A);
+                    self.active_formatting.retain(|entry| *entry != FormattingEntry::Element(a));
+                    self.open_elements.retain(|id| *id != a);
+                }
+                self.reconstruct_active_formatting();
+                self.insert_formatting(element);
+            },
+            tag if tag.is_formatting() => {
+                self.reconstruct_active_formatting();
+                self.insert_formatting(element);
+            },
+            HtmlTag::TD | HtmlTag::TH => {
+                self.close_cell();
+                if self.insert_element(element).is_some() {
+                    self.active_formatting.push(FormattingEntry::Marker);
+                }
+            },
+            HtmlTag::TR => {
+                self.close_cell();
+                if self.has_in_scope(|t| *t == HtmlTag::TR, Scope::Table) {
+                    self.pop_until(|t| *t == HtmlTag::TR);
+                }
+                self.insert_element(element);
+            },
+            HtmlTag::THEAD | HtmlTag::TBODY | HtmlTag::TFOOT => {
+                let is_section = |t: &HtmlTag| matches!(t, HtmlTag::THEAD | HtmlTag::TBODY | HtmlTag::TFOOT);
+                if self.has_in_scope(is_section, Scope::Table) {
+                    self.pop_until(is_section);
+                }
+                self.insert_element(element);
+            },
+            HtmlTag::CAPTION | HtmlTag::TEMPLATE => {
+                if self.insert_element(element).is_some() {
+                    self.active_formatting.push(FormattingEntry::Marker);
+                }
+            },
+            _ => {
+                self.reconstruct_active_formatting();
+                self.insert_element(element);
+            }
+        }
+    }
+
+    fn end_tag(&mut self, tag: HtmlTag) {
+        // Those left out inside an element that has been closed since are
+        // gone with it.
+        let open = self.open_elements.len();
+        self.dropped.retain(|(_, depth)| *depth <= open);
+        if let Some(i) = self.dropped.iter().rposition(|(dropped, _)| *dropped == tag) {
+            self.dropped.remove(i);
+            return;
+        }
+
+        match &tag {
+            // Content after </body> or </html> still belongs in the body.
+            HtmlTag::HTML | HtmlTag::BODY => {},
+            HtmlTag::P => {
+                if !self.has_in_scope(|t| *t == HtmlTag::P, Scope::Default) {
+                    self.report(ParseErrorKind::StrayEndTag, || "end tag </p> without an open <p>".to_string());
+                    if self.insert_element(HtmlElement::new(HtmlTag::P, Attributes::new(), false, false)).is_none() {
+                        // It has no end tag of its own to wait for.
+                        self.dropped.pop();
+                        return;
+                    }
+                }
+                self.close_p();
+            },
+            HtmlTag::LI => {
+                match self.has_in_scope(|t| *t == HtmlTag::LI, Scope::ListItem) {
+                    true => {
+                        self.generate_implied_end_tags(Some(&HtmlTag::LI));
+                        self.check_current_node(&tag, |t| *t == HtmlTag::LI);
+                        self.pop_until(|t| *t == HtmlTag::LI);
+                    },
+                    false => self.report_stray(&tag),
+                }
+            },
+            tag if tag.is_heading() => {
+                match self.has_in_scope(|t| t.is_heading(), Scope::Default) {
+                    true => {
+                        self.generate_implied_end_tags(None);
+                        self.check_current_node(tag, |t| t == tag);
+                        self.pop_until(|t| t.is_heading());
+                    },
+                    false => self.report_stray(tag),
+                }
+            },
+            // Browsers read a stray `</br>` as `<br>` and drop any other end
+            // tag for a void element.
+            HtmlTag::BR => {
+                self.report(ParseErrorKind::StrayEndTag, || "end tag </br> treated as <br>".to_string());
+                self.reconstruct_active_formatting();
+                self.insert_element(HtmlElement::new(HtmlTag::BR, Attributes::new(), false, false));
+            },
+            tag if tag.is_void() => self.report_stray(tag),
+            tag if tag.is_formatting() => {
+                if !self.adoption_agency(tag) {
+                    self.any_other_end_tag(tag);
+                }
+            },
+            HtmlTag::TABLE | HtmlTag::TR | HtmlTag::THEAD | HtmlTag::TBODY | HtmlTag::TFOOT |
+            HtmlTag::TD | HtmlTag::TH | HtmlTag::CAPTION | HtmlTag::TEMPLATE => {
+                match self.has_in_scope(|t| *t == tag, Scope::Table) {
+                    true => {
+                        self.generate_implied_end_tags(None);
+                        self.check_current_node(&tag, |t| *t == tag);
+                        self.pop_until(|t| *t == tag);
+                    },
+                    false => self.report_stray(&tag),
+                }
+            },
+            tag if is_block_container(tag) => {
+                match self.has_in_scope(|t| t == tag, Scope::Default) {
+                    true => {
+                        self.generate_implied_end_tags(None);
+                        self.check_current_node(tag, |t| t == tag);
+                        self.pop_until(|t| t == tag);
+                    },
+                    false => self.report_stray(tag),
+                }
+            },
+            tag => self.any_other_end_tag(tag),
+        }
+    }
+
+    fn report(&mut self, kind: ParseErrorKind, message: impl FnOnce() -> String) {
+        self.diagnostics.report(kind, self.span, message);
+    }
+
+    fn report_stray(&mut self, tag: &HtmlTag) {
+        self.report(ParseErrorKind::StrayEndTag, || format!("unexpected end tag </{}>", tag.name()));
+    }
+
+    // An end tag that is about to close elements other than its own, like
+    // the </div> in `<div><span>text</div>`.
+    fn check_current_node(&mut self, tag: &HtmlTag, matches: impl Fn(&HtmlTag) -> bool) {
+        let Some(current) = self.current_tag().cloned() else {
+            return;
+        };
+        if !matches(&current) {
+            self.report(ParseErrorKind::MisnestedTag, || {
+                format!("end tag </{}> closes unclosed <{}>", tag.name(), current.name())
+            });
+        }
+    }
+
+    fn current_node(&self) -> NodeId {
+        self.open_elements.last().copied().unwrap_or(self.document.root())
+    }
+
+    fn current_tag(&self) -> Option<&HtmlTag> {
+        self.open_elements.last().and_then(|id| self.tag_of(*id))
+    }
+
+    fn tag_of(&self, id: NodeId) -> Option<&HtmlTag> {
+        self.document.node(id).tag()
+    }
+
+    fn has_open(&self, tag: &HtmlTag) -> bool {
+        self.open_elements.iter().any(|id| self.tag_of(*id) == Some(tag))
+    }
+
+    fn has_in_scope(&self, matches: impl Fn(&HtmlTag) -> bool, scope: Scope) -> bool {
+        for id in self.open_elements.iter().rev() {
+            let Some(tag) = self.tag_of(*id) else {
+                continue;
+            };
+            if matches(tag) {
+                return true;
+            }
+            if is_scope_boundary(tag, scope) {
+                return false;
+            }
+        }
+
+        false
+    }
+
+    fn has_node_in_scope(&self, node: NodeId) -> bool {
+        for id in self.open_elements.iter().rev() {
+            if *id == node {
+                return true;
+            }
+            if self.tag_of(*id).is_some_and(|tag| is_scope_boundary(tag, Scope::Default)) {
+                return false;
+            }
+        }
+
+        false
+    }
+
+    // Returns None if the element was left out for being nested too deep.
+    fn insert_element(&mut self, element: HtmlElement) -> Option<NodeId> {
+        let parent = self.current_node();
+        let empty = element.is_empty();
+
+        if !empty && self.max_depth.is_some_and(|max_depth| self.open_elements.len() >= max_depth) {
+            self.depth_exceeded.get_or_insert(self.span);
+            self.dropped.push((element.tag, self.open_elements.len()));
+            return None;
+        }
+
+        let id = self.document.append(parent, NodeData::Element(element), self.span);
+
+        if !empty {
+            self.open_elements.push(id);
+        }
+
+        Some(id)
+    }
+
+    fn insert_formatting(&mut self, element: HtmlElement) {
+        // "Noah's Ark" clause: keep at most three identical entries after
+        // the last marker so `<b><b><b><b>...` cannot grow without bound.
+        let identical: Vec<usize> = self.active_formatting.iter()
+            .enumerate()
+            .rev()
+            .take_while(|(_, entry)| **entry != FormattingEntry::Marker)
+            .filter_map(|(i, entry)| match entry {
+                FormattingEntry::Element(id) if self.document.node(*id).element() == Some(&element) => Some(i),
+                _ => None,
+            })
+            .collect();
+
+        if identical.len() >= 3 {
+            if let Some(earliest) = identical.last() {
+                self.active_formatting.remove(*earliest);
+            }
+        }
+
+        if let Some(id) = self.insert_element(element) {
+            self.active_formatting.push(FormattingEntry::Element(id));
+        }
+    }
+
+    fn pop(&mut self) -> Option<NodeId> {
+        let id = self.open_elements.pop()?;
+
+        // An element closed by its own end tag ends after that tag, one
+        // closed implicitly ends where the token that closed it starts.
+        let end = match self.tag_of(id).is_some_and(|tag| self.closing_tag.as_ref() == Some(tag)) {
+            true => {
+                self.closing_tag = None;
+                self.span.end
+            },
+            false => self.span.start,
+        };
+        self.document.set_span_end(id, end);
+
+        if self.tag_of(id).is_some_and(pushes_marker) {
+            self.clear_to_last_marker();
+        }
+
+        Some(id)
+    }
+
+    fn pop_until(&mut self, matches: impl Fn(&HtmlTag) -> bool) {
+        while let Some(id) = self.pop() {
+            if self.tag_of(id).is_some_and(&matches) {
+                break;
+            }
+        }
+    }
+
+    fn generate_implied_end_tags(&mut self, except: Option<&HtmlTag>) {
+        while let Some(tag) = self.current_tag() {
+            if !matches!(tag, HtmlTag::P | HtmlTag::LI | HtmlTag::OPTGROUP) || Some(tag) == except {
+                break;
+            }
+            self.pop();
+        }
+    }
+
+    fn close_p(&mut self) {
+        self.generate_implied_end_tags(Some(&HtmlTag::P));
+        self.pop_until(|t| *t == HtmlTag::P);
+    }
+
+    fn close_list_item(&mut self) {
+        for id in self.open_elements.clone().into_iter().rev() {
+            let Some(tag) = self.tag_of(id) else {
+                continue;
+            };
+
+            if *tag == HtmlTag::LI {
+                self.generate_implied_end_tags(Some(&HtmlTag::LI));
+                self.pop_until(|t| *t == HtmlTag::LI);
+                return;
+            }
+
+            if tag.is_special() && !matches!(tag, HtmlTag::DIV | HtmlTag::P) {
+                return;
+            }
+        }
+    }
+
+    fn close_cell(&mut self) {
+        if self.has_in_scope(|t| matches!(t, HtmlTag::TD | HtmlTag::TH), Scope::Table) {
+            self.generate_implied_end_tags(None);
+            self.pop_until(|t| matches!(t, HtmlTag::TD | HtmlTag::TH));
+        }
+    }
+
+    fn clear_to_last_marker(&mut self) {
+        while let Some(entry) = self.active_formatting.pop() {
+            if entry == FormattingEntry::Marker {
+                break;
+            }
+        }
+    }
+
+    fn formatting_after_marker(&self, tag: &HtmlTag) -> Option<NodeId> {
+        for entry in self.active_formatting.iter().rev() {
+            match entry {
+                FormattingEntry::Marker => return None,
+                FormattingEntry::Element(id) if self.tag_of(*id) == Some(tag) => return Some(*id),
+                FormattingEntry::Element(_) => {}
+            }
+        }
+
+        None
+    }
+
+    fn formatting_position(&self, node: NodeId) -> Option<usize> {
+        self.active_formatting.iter().position(|entry| *entry == FormattingEntry::Element(node))
+    }
+
+    fn clone_element(&mut self, node: NodeId) -> NodeId {
+        let data = self.document.node(node).data.clone();
+        let span = self.document.span(node);
+        self.document.create(data, span)
+    }
+
+    // Re-opens formatting elements that were implicitly closed, so the `b`
+    // in `<p><b>one<p>two` still applies to "two".
+    fn reconstruct_active_formatting(&mut self) {
+        let Some(FormattingEntry::Element(last)) = self.active_formatting.last() else {
+            return;
+        };
+        if self.open_elements.contains(last) {
+            return;
+        }
+
+        let mut index = self.active_formatting.len() - 1;
+        while index > 0 {
+            match self.active_formatting[index - 1] {
+                FormattingEntry::Marker => break,
+                FormattingEntry::Element(id) if self.open_elements.contains(&id) => break,
+                FormattingEntry::Element(_) => index -= 1,
+            }
+        }
+
+        for i in index..self.active_formatting.len() {
+            let FormattingEntry::Element(id) = self.active_formatting[i] else {
+                continue;
+            };
+            let Some(element) = self.document.node(id).element().cloned() else {
+                continue;
+            };
+            let Some(new_id) = self.insert_element(element) else {
+                return;
+            };
+            self.active_formatting[i] = FormattingEntry::Element(new_id);
+        }
+    }
+
+    // The HTML5 adoption agency algorithm. Returns false when there is no
+    // matching formatting element, in which case the end tag is handled like
+    // any other.
+    fn adoption_agency(&mut self, tag: &HtmlTag) -> bool {
+        let current = self.current_node();
+        if self.tag_of(current) == Some(tag) && self.formatting_position(current).is_none() {
+            self.pop();
+            return true;
+        }
+
+        for round in 0..8 {
+            let Some(formatting_element) = self.formatting_after_marker(tag) else {
+                return false;
+            };
+
+            let Some(formatting_stack_index) = self.open_elements.iter().position(|id| *id == formatting_element) else {
+                self.report_stray(tag);
+                self.active_formatting.retain(|entry| *entry != FormattingEntry::Element(formatting_element));
+                return true;
+            };
+
+            if !self.has_node_in_scope(formatting_element) {
+                self.report_stray(tag);
+                return true;
+            }
+
+            if round == 0 {
+                self.check_current_node(tag, |t| t == tag);
+            }
+
+            let furthest_block_index = self.open_elements[formatting_stack_index + 1..]
+                .iter()
+                .position(|id| self.tag_of(*id).is_some_and(|t| t.is_special()))
+                .map(|i| i + formatting_stack_index + 1);
+
+            let Some(furthest_block_index) = furthest_block_index else {
+                while self.open_elements.len() > formatting_stack_index {
+                    self.pop();
+                }
+                self.active_formatting.retain(|entry| *entry != FormattingEntry::Element(formatting_element));
+                return true;
+            };
+
+            let furthest_block = self.open_elements[furthest_block_index];
+            let common_ancestor = match formatting_stack_index {
+                0 => self.document.root(),
+                i => self.open_elements[i - 1],
+            };
+
+            let mut bookmark = self.formatting_position(formatting_element).unwrap_or(0);
+            let mut node_index = furthest_block_index;
+            let mut last_node = furthest_block;
+            let mut inner = 0;
+
+            loop {
+                inner += 1;
+                node_index -= 1;
+                let node = self.open_elements[node_index];
+
+                if node == formatting_element {
+                    break;
+                }
+
+                let mut node_position = self.formatting_position(node);
+                if inner > 3 {
+                    if let Some(position) = node_position.take() {
+                        self.active_formatting.remove(position);
+                        if position < bookmark {
+                            bookmark -= 1;
+                        }
+                    }
+                }
+
+                let Some(node_position) = node_position else {
+                    self.open_elements.remove(node_index);
+                    continue;
+                };
+
+                let new_node = self.clone_element(node);
+                self.active_formatting[node_position] = FormattingEntry::Element(new_node);
+                self.open_elements[node_index] = new_node;
+
+                if last_node == furthest_block {
+                    bookmark = node_position + 1;
+                }
+
+                self.document.append_child(new_node, last_node);
+                last_node = new_node;
+            }
+
+            self.document.append_child(common_ancestor, last_node);
+
+            let new_element = self.clone_element(formatting_element);
+            self.document.move_children(furthest_block, new_element);
+            self.document.append_child(furthest_block, new_element);
+
+            if let Some(position) = self.formatting_position(formatting_element) {
+                self.active_formatting.remove(position);
+                if position < bookmark {
+                    bookmark -= 1;
+                }
+            }
+            let bookmark = bookmark.min(self.active_formatting.len());
+            self.active_formatting.insert(bookmark, FormattingEntry::Element(new_element));
+
+            self.open_elements.retain(|id| *id != formatting_element);
+            if let Some(position) = self.open_elements.iter().position(|id| *id == furthest_block) {
+                self.open_elements.insert(position + 1, new_element);
+            }
+        }
+
+        true
+    }
+
+    fn any_other_end_tag(&mut self, tag: &HtmlTag) {
+        for index in (0..self.open_elements.len()).rev() {
+            let node = self.open_elements[index];
+            let Some(node_tag) = self.tag_of(node) else {
+                continue;
+            };
+
+            if node_tag == tag {
+                self.generate_implied_end_tags(Some(tag));
+                if self.current_node() != node {
+                    self.check_current_node(tag, |_| false);
+                }
+                while let Some(id) = self.pop() {
+                    if id == node {
+                        break;
+                    }
+                }
+                return;
+            }
+
+            if node_tag.is_special() {
+                self.report_stray(tag);
+                return;
+            }
+        }
+
+        self.report_stray(tag);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tuihtml::{dom::{Document, NodeData, NodeId}, html::HtmlTag, parser::parse_document, tokenizer::{HtmlTokenizer, Token}, treebuilder::TreeBuilder};
+
+    // Serializes the tree back to compact markup so the expected structure
+    // can be written inline.
+    fn outline(document: &Document, id: NodeId) -> String {
+        let children: String = document.children(id).map(|child| outline(document, child)).collect();
+
+        match &document.node(id).data {
+            NodeData::Element(element) => {
+                let tag = match &element.tag {
+                    HtmlTag::UNKNOWN(name) => name.clone(),
+                    tag => format!("{:?}", tag).to_lowercase(),
+                };
+                match element.is_empty() {
+                    true => format!("<{}>", tag),
+                    false => format!("<{}>{}</{}>", tag, children, tag),
+                }
+            },
+            NodeData::Text(text) => text.clone(),
+            NodeData::Comment(_) => String::new(),
+            NodeData::Document => children,
+        }
+    }
+
+    fn parse(html: &str) -> String {
+        let document = parse_document(html);
+        outline(&document, document.root())
+    }
+
+    #[test]
+    fn implied_end_tags_close_paragraphs_and_list_items() {
+        assert_eq!(parse("<p>one<p>two"), "<p>one</p><p>two</p>");
+        assert_eq!(parse("<p>one<div>two</div>"), "<p>one</p><div>two</div>");
+        assert_eq!(parse("<ul><li>a<li>b</ul>after"), "<ul><li>a</li><li>b</li></ul>after");
+        assert_eq!(parse("<ol><li>a<ul><li>b</ul><li>c</ol>"), "<ol><li>a<ul><li>b</li></ul></li><li>c</li></ol>");
+        assert_eq!(parse("<h1>one<h2>two</h1>three"), "<h1>one</h1><h2>two</h2>three");
+        assert_eq!(parse("text</p>"), "text<p></p>");
+    }
+
+    #[test]
+    fn stray_end_tags_are_ignored() {
+        assert_eq!(parse("<p>one</b>two</span></p>three"), "<p>onetwo</p>three");
+        assert_eq!(parse("<div><span>one</div>two"), "<div><span>one</span></div>two");
+        assert_eq!(parse("<div>one</section>two</div>"), "<div>onetwo</div>");
+    }
+
+    #[test]
+    fn unknown_end_tags_close_elements_of_their_own_name() {
+        assert_eq!(parse("<foo><bar>one</foo>two</bar>"), "<foo><bar>one</bar></foo>two");
+        assert_eq!(parse("<foo>one</bar>two</foo>"), "<foo>onetwo</foo>");
+        assert_eq!(parse("<Foo><bar>one</bar>two</FOO>three"), "<foo><bar>one</bar>two</foo>three");
+    }
+
+    #[test]
+    fn misnested_formatting_is_adopted() {
+        assert_eq!(parse("<b>1<i>2</b>3</i>4"), "<bold>1<i>2</i></bold><i>3</i>4");
+        assert_eq!(parse("<b>1<p>2</b>3</p>"), "<bold>1</bold><p><bold>2</bold>3</p>");
+        assert_eq!(parse("<p><b>one<p>two</b>three"), "<p><bold>one</bold></p><p><bold>two</bold>three</p>");
+        assert_eq!(parse("<a>one<a>two</a>"), "<a>one</a><a>two</a>");
+    }
+
+    #[test]
+    fn end_tags_of_elements_left_out_for_depth_are_ignored() {
+        let parse = |html: &str| {
+            let mut tokenizer = HtmlTokenizer::new(html.chars());
+            let mut tree_builder = TreeBuilder::new();
+            tree_builder.set_max_depth(2);
+            loop {
+                let (token, span) = tokenizer.next_with_span();
+                let eof = token == Token::Eof;
+                tree_builder.process(token, span);
+                if eof {
+                    break;
+                }
+            }
+            let document = tree_builder.finish();
+            outline(&document, document.root())
+        };
+
+        assert_eq!(parse("<div><div><div>x</div>y</div>z</div>after"), "<div><div>xy</div>z</div>after");
+        // Gone with the element they were left out of.
+        assert_eq!(parse("<div><div><span>x</div></span>y</div>"), "<div><div>x</div>y</div>");
+        assert_eq!(parse("<div><div></p>x</div>y</p>z</div>"), "<div><div>x</div>y<p></p>z</div>");
+    }
+
+    #[test]
+    fn cdata_is_text_only_in_svg_and_math() {
+        assert_eq!(parse("<p>a<![CDATA[<b>]]>c</p>"), "<p>ac</p>");
+        assert_eq!(parse("<svg><text><![CDATA[a < b]]></text></svg>"), "<svg><text>a < b</text></svg>");
+        assert_eq!(parse("<math><![CDATA[x]]></math><![CDATA[y]]>"), "<math>x</math>");
+    }
+}
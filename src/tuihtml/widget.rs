@@ -1,24 +1,113 @@
-use ratatui::widgets::Paragraph;
+use std::{cell::{Ref, RefCell}, io::{self, Read}};
 
-use crate::tuihtml::parser::construct_widget;
+use ratatui::{buffer::Buffer, layout::Rect, text::Line, widgets::{Paragraph, Widget, Wrap}};
 
-#[derive(Default)]
+use crate::tuihtml::{diagnostics::ParseError, dom::Document, error::Result, layout::{fit_lines, LineLayout}, options::{Limit, ParseOptions}, parser::{construct_widget_from_bytes, construct_widget_from_document, construct_widget_from_document_with_theme, construct_widget_from_reader, construct_widget_with_diagnostics, construct_widget_with_options, try_construct_widget_from_document, try_parse_document, try_parse_document_from_bytes, try_parse_document_from_reader}, source::SourceSpan, table::TableLines, theme::{RuleLine, Theme}};
+
+#[derive(Debug, Default)]
 pub struct HtmlWidget<'a> {
-    pub paragraph: Paragraph<'a>,
-    pub links: Vec<String>,
-    pub images: Vec<String>,
+    // The lines with rules drawn for no particular width, see
+    // `get_paragraph_for_width`.
+    pub(crate) paragraph: Paragraph<'a>,
+    pub(crate) lines: Vec<Line<'a>>,
+    // How each of `lines` is indented, to wrap it inside its blocks.
+    pub(crate) line_layouts: Vec<LineLayout>,
+    // Which of `lines` are rules, like the one an <hr> draws, and which are
+    // tables.
+    pub(crate) rules: Vec<RuleLine>,
+    pub(crate) tables: Vec<TableLines<'a>>,
+    pub(crate) links: Vec<String>,
+    pub(crate) images: Vec<String>,
+    pub(crate) document: Document,
+    // Where in the source each line of `paragraph` came from, before the
+    // paragraph wraps it to the render area.
+    pub(crate) line_sources: Vec<Option<SourceSpan>>,
+    // The paragraph last fitted to a width, if any, so that rendering at the
+    // same width frame after frame does not lay the lines out again.
+    pub(crate) fitted: RefCell<(Option<u16>, Paragraph<'a>)>,
 }
 
 impl<'a> HtmlWidget<'a> {
 
-    pub fn new(html: String) -> Self {
-        construct_widget(html)
+    // Uses the default theme, whose colors do not depend on the terminal or
+    // NO_COLOR. Pass `Theme::for_terminal()` to `build` for that.
+    pub fn new(html: &str) -> Self {
+        Self::build(html, None, None).0
+    }
+
+    // Draws the elements as `theme` says instead of in the default look, and
+    // renders at most what fits in the limits of `options`, or the default
+    // ones, also returning the limits that cut the document short.
+    pub fn build(html: &str, theme: Option<&Theme>, options: Option<&ParseOptions>) -> (Self, Vec<Limit>) {
+        let (default_theme, default_options) = (Theme::default(), ParseOptions::default());
+        construct_widget_with_options(html, theme.unwrap_or(&default_theme), options.unwrap_or(&default_options))
+    }
+
+    // Like `build`, but fails instead of rendering a document that exceeds
+    // any of the limits in `options`, or the default ones.
+    pub fn try_build(html: &str, theme: Option<&Theme>, options: Option<&ParseOptions>) -> Result<Self> {
+        let (default_theme, default_options) = (Theme::default(), ParseOptions::default());
+        let options = options.unwrap_or(&default_options);
+        try_construct_widget_from_document(try_parse_document(html, options)?, theme.unwrap_or(&default_theme), options)
+    }
+
+    // Builds the widget and also returns the parse errors recovered from on
+    // the way.
+    pub fn with_diagnostics(html: &str) -> (Self, Vec<ParseError>) {
+        construct_widget_with_diagnostics(html)
+    }
+
+    // For pages fetched as bytes in whatever encoding they were served in.
+    // `hint` is a charset label such as the one in an HTTP Content-Type.
+    pub fn from_bytes(bytes: &[u8], hint: Option<&str>) -> Self {
+        construct_widget_from_bytes(bytes, hint)
+    }
+
+    // Reads the HTML from `reader`, e.g. a file or stdin, without loading
+    // it into a string first.
+    pub fn from_reader(reader: impl Read) -> io::Result<Self> {
+        construct_widget_from_reader(reader)
+    }
+
+    // Like `try_build`, and also fails if `bytes` are not valid in the
+    // encoding they declare.
+    pub fn try_from_bytes(bytes: &[u8], hint: Option<&str>, theme: Option<&Theme>, options: Option<&ParseOptions>) -> Result<Self> {
+        let (default_theme, default_options) = (Theme::default(), ParseOptions::default());
+        let options = options.unwrap_or(&default_options);
+        try_construct_widget_from_document(try_parse_document_from_bytes(bytes, hint, options)?, theme.unwrap_or(&default_theme), options)
+    }
+
+    pub fn try_from_reader(reader: impl Read, theme: Option<&Theme>, options: Option<&ParseOptions>) -> Result<Self> {
+        let (default_theme, default_options) = (Theme::default(), ParseOptions::default());
+        let options = options.unwrap_or(&default_options);
+        try_construct_widget_from_document(try_parse_document_from_reader(reader, options)?, theme.unwrap_or(&default_theme), options)
+    }
+
+    pub fn from_document(document: Document, theme: Option<&Theme>) -> Self {
+        match theme {
+            Some(theme) => construct_widget_from_document_with_theme(document, theme),
+            None => construct_widget_from_document(document),
+        }
     }
 
     pub fn get_paragraph(&self) -> Paragraph<'_> {
         self.paragraph.clone()
     }
 
+    // The paragraph laid out for an area `width` columns wide: rules fit it
+    // and text wraps inside the blocks it is in. Rendering the widget itself
+    // does this for the area it is given.
+    pub fn get_paragraph_for_width(&self, width: u16) -> Paragraph<'a> {
+        self.fitted(width).clone()
+    }
+
+    fn fitted(&self, width: u16) -> Ref<'_, Paragraph<'a>> {
+        if self.fitted.borrow().0 != Some(width) {
+            *self.fitted.borrow_mut() = (Some(width), paragraph_for_width(&self.lines, &self.line_layouts, &self.rules, &self.tables, width));
+        }
+        Ref::map(self.fitted.borrow(), |(_, paragraph)| paragraph)
+    }
+
     pub fn get_links(&self) -> Vec<String> {
         self.links.clone()
     }
@@ -26,4 +115,22 @@ impl<'a> HtmlWidget<'a> {
     pub fn get_images(&self) -> Vec<String> {
         self.images.clone()
     }
+
+    pub fn get_document(&self) -> &Document {
+        &self.document
+    }
+
+    pub fn get_line_source(&self, line: usize) -> Option<SourceSpan> {
+        self.line_sources.get(line).copied().flatten()
+    }
+}
+
+impl Widget for &HtmlWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        Widget::render(&*self.fitted(area.width), area, buf);
+    }
+}
+
+pub(crate) fn paragraph_for_width<'a>(lines: &[Line<'a>], layouts: &[LineLayout], rules: &[RuleLine], tables: &[TableLines<'a>], width: u16) -> Paragraph<'a> {
+    Paragraph::new(fit_lines(lines, layouts, rules, tables, usize::from(width))).wrap(Wrap { trim: false })
 }